@@ -0,0 +1,153 @@
+// Finds generated and non-diffable files, which only add noise to the results.
+// Files are marked in `.gitattributes` with `linguist-generated`, `-diff` or
+// `binary`, and some well known ones are skipped even when they aren't.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::str;
+
+use git2::{Repository, Tree};
+
+use pathspec;
+
+/// File names which are generated, unless `.gitattributes` says otherwise.
+pub const GENERATED: &'static [&'static str] = &[
+    "Cargo.lock", "package-lock.json", "yarn.lock", "*.min.js",
+];
+
+// A line of a `.gitattributes` file, `None` when it doesn't set the attribute.
+struct Rule {
+    pattern: String,
+    generated: Option<bool>,
+    diff: Option<bool>,
+}
+
+fn parse_rules(source: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in source.lines() {
+        let mut words = line.split(|c: char| c.is_whitespace()).filter(|word| !word.is_empty());
+        let pattern = match words.next() {
+            Some(pattern) if !pattern.starts_with("#") => pattern,
+            _ => continue,
+        };
+        let mut rule = Rule {
+            pattern: String::from_str(pattern),
+            generated: None,
+            diff: None,
+        };
+        for word in words {
+            match word {
+                "linguist-generated" | "linguist-generated=true" => rule.generated = Some(true),
+                "-linguist-generated" | "linguist-generated=false" => rule.generated = Some(false),
+                // `binary` is short for `-diff -merge -text`.
+                "-diff" | "binary" => rule.diff = Some(false),
+                "diff" => rule.diff = Some(true),
+                _ => (),
+            }
+        }
+        rules.push(rule);
+    }
+    rules
+}
+
+// Whether `pattern`, from the `.gitattributes` in `directory`, matches `path`.
+// Patterns without a `/` match the file name in any directory below it.
+fn rule_matches(pattern: &str, directory: &str, path: &str) -> bool {
+    let relative = &path[directory.len()..];
+    let (pattern, subject) = if pattern.contains('/') {
+        (pattern.trim_left_matches('/'), relative)
+    } else {
+        (pattern, relative.rsplit('/').next().unwrap_or(relative))
+    };
+    let pattern: Vec<char> = pattern.chars().collect();
+    let subject: Vec<char> = subject.chars().collect();
+    pathspec::glob(&pattern, &subject, false)
+}
+
+fn is_generated_name(path: &str) -> bool {
+    let name: Vec<char> = path.rsplit('/').next().unwrap_or(path).chars().collect();
+    GENERATED.iter().any(|pattern| {
+        let pattern: Vec<char> = pattern.chars().collect();
+        pathspec::glob(&pattern, &name, false)
+    })
+}
+
+/// The `.gitattributes` files of a tree, read as they are needed.
+pub struct Attributes<'a> {
+    repo: &'a Repository,
+    tree: &'a Tree<'a>,
+    // Rules by the directory they apply to, eg. `""` or `"src/"`.
+    rules: HashMap<String, Vec<Rule>>,
+}
+
+impl<'a> Attributes<'a> {
+    pub fn new(repo: &'a Repository, tree: &'a Tree<'a>) -> Attributes<'a> {
+        Attributes {
+            repo: repo,
+            tree: tree,
+            rules: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, directory: &str) {
+        if self.rules.contains_key(directory) { return; }
+        let path = format!("{}.gitattributes", directory);
+        let rules = match self.tree.get_path(Path::new(&path)) {
+            Ok(entry) => match self.repo.find_blob(entry.id()) {
+                Ok(blob) => parse_rules(str::from_utf8(blob.content()).unwrap_or("")),
+                Err(_) => Vec::new(),
+            },
+            Err(_) => Vec::new(),
+        };
+        self.rules.insert(String::from_str(directory), rules);
+    }
+
+    /// Whether the file at `path` is generated or not meant to be diffed.
+    pub fn skips(&mut self, path: &str) -> bool {
+        // Every directory the file is in, outermost first, so deeper
+        // `.gitattributes` files and later lines win.
+        let mut directories = vec![String::new()];
+        for (index, c) in path.char_indices() {
+            if c == '/' { directories.push(String::from_str(&path[..index + 1])); }
+        }
+        let (mut generated, mut diff) = (None, None);
+        for directory in directories.iter() {
+            self.load(directory);
+            for rule in self.rules.get(directory).unwrap().iter() {
+                if !rule_matches(&rule.pattern, directory, path) { continue; }
+                generated = rule.generated.or(generated);
+                diff = rule.diff.or(diff);
+            }
+        }
+        diff == Some(false) || generated.unwrap_or_else(|| is_generated_name(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_rules, rule_matches, is_generated_name};
+
+    #[test]
+    fn rules() {
+        let rules = parse_rules("# Comment\n*.pb.go linguist-generated\nassets/** -diff\nCargo.lock -linguist-generated\n");
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].generated, Some(true));
+        assert_eq!(rules[1].diff, Some(false));
+        assert_eq!(rules[2].generated, Some(false));
+    }
+
+    #[test]
+    fn matching() {
+        assert!(rule_matches("*.pb.go", "", "api/v1/service.pb.go"));
+        assert!(rule_matches("/gen/*.rs", "src/", "src/gen/out.rs"));
+        assert!(!rule_matches("/gen/*.rs", "src/", "src/gen/deep/out.rs"));
+        assert!(rule_matches("assets/**", "", "assets/a/b.svg"));
+    }
+
+    #[test]
+    fn generated_names() {
+        assert!(is_generated_name("Cargo.lock"));
+        assert!(is_generated_name("web/static/app.min.js"));
+        assert!(!is_generated_name("web/static/app.js"));
+    }
+}
@@ -0,0 +1,205 @@
+// Keeps analysed commit pairs on disk so later runs only analyse new commits.
+// Each result is a JSON file at `<directory>/<fingerprint>/<old>-<new>.json`,
+// where the fingerprint changes whenever the options which shape the results do.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use git2::{Oid, Repository};
+use rustc_serialize::{json, Decodable, Encodable};
+
+fn is_dir(path: &Path) -> bool {
+    fs::metadata(path).map(|metadata| metadata.is_dir()).unwrap_or(false)
+}
+
+/// Whether `fingerprint` looks like one from `Options::fingerprint`, 16 hex
+/// digits, so it can't name anything outside of the cache.
+pub fn is_fingerprint(fingerprint: &str) -> bool {
+    fingerprint.len() == 16 && fingerprint.chars().all(|c| c.is_digit(16))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned())
+}
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    directory: PathBuf,
+}
+
+/// A cached result.
+#[derive(Debug, RustcEncodable)]
+pub struct Entry {
+    fingerprint: String,
+    old: String,
+    new: String,
+    bytes: u64,
+}
+
+impl Cache {
+    pub fn new(directory: PathBuf) -> Cache {
+        Cache { directory: directory }
+    }
+
+    /// The default cache, in `.git/transit/`.
+    pub fn for_repo(repo: &Repository) -> Cache {
+        Cache::new(repo.path().join("transit"))
+    }
+
+    fn path(&self, fingerprint: &str, old: Oid, new: Oid) -> PathBuf {
+        self.directory.join(fingerprint).join(&format!("{}-{}.json", old, new))
+    }
+
+    /// The cached result for `old` and `new`. A result which can't be read is
+    /// treated like a missing one, and will be analysed again.
+    pub fn load<T: Decodable>(&self, fingerprint: &str, old: Oid, new: Oid) -> Option<T> {
+        let mut file = match File::open(&self.path(fingerprint, old, new)) {
+            Ok(file) => file,
+            Err(_) => return None,
+        };
+        let mut contents = String::new();
+        if file.read_to_string(&mut contents).is_err() { return None; }
+        json::decode(&contents).ok()
+    }
+
+    pub fn store<T: Encodable>(&self, fingerprint: &str, old: Oid, new: Oid, value: &T) -> io::Result<()> {
+        try!(fs::create_dir_all(&self.directory.join(fingerprint)));
+        let encoded = match json::encode(value) {
+            Ok(encoded) => encoded,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "Unable to encode the result.")),
+        };
+        let mut file = try!(File::create(&self.path(fingerprint, old, new)));
+        file.write_all(encoded.as_bytes())
+    }
+
+    /// Everything in the cache.
+    pub fn entries(&self) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        if !is_dir(&self.directory) { return Ok(entries); }
+        for fingerprint in try!(fs::read_dir(&self.directory)) {
+            let fingerprint = try!(fingerprint).path();
+            if !is_dir(&fingerprint) { continue; }
+            for file in try!(fs::read_dir(&fingerprint)) {
+                let file = try!(file).path();
+                let name = file_name(&file);
+                let ids: Vec<&str> = name.trim_right_matches(".json").split('-').collect();
+                if ids.len() != 2 { continue; }
+                entries.push(Entry {
+                    fingerprint: file_name(&fingerprint),
+                    old: String::from_str(ids[0]),
+                    new: String::from_str(ids[1]),
+                    bytes: try!(fs::metadata(&file)).len(),
+                });
+            }
+        }
+        entries.sort_by(|a, b| (&a.fingerprint, &a.old, &a.new).cmp(&(&b.fingerprint, &b.old, &b.new)));
+        Ok(entries)
+    }
+
+    /// Removes results for commits which are no longer in `repo`, eg. after a
+    /// rebase. Returns how many were removed.
+    pub fn prune(&self, repo: &Repository) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in try!(self.entries()).iter() {
+            let exists = |id: &str| Oid::from_str(id).ok().map_or(false, |id| repo.find_commit(id).is_ok());
+            if exists(&entry.old) && exists(&entry.new) { continue; }
+            try!(fs::remove_file(&self.directory.join(&entry.fingerprint)
+                                     .join(&format!("{}-{}.json", entry.old, entry.new))));
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Removes the results for one fingerprint, or all of them.
+    pub fn invalidate(&self, fingerprint: Option<&str>) -> io::Result<()> {
+        let directory = match fingerprint {
+            Some(fingerprint) if !is_fingerprint(fingerprint) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a fingerprint."));
+            },
+            Some(fingerprint) => self.directory.join(fingerprint),
+            None => self.directory.clone(),
+        };
+        if is_dir(&directory) {
+            try!(fs::remove_dir_all(&directory));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Oid, Repository};
+    use rustc_serialize::json;
+
+    use super::{is_fingerprint, Cache};
+    use fixture;
+    use processor::{self, OutputSet};
+    use revisions::Revisions;
+
+    const F: &'static str = "0123456789abcdef";
+    const G: &'static str = "fedcba9876543210";
+
+    // Two commits, and what was found between them.
+    fn analysed(repo: &Repository) -> (Oid, Oid, OutputSet) {
+        let old = fixture::commit(repo, &[("a.rs", "fn a() { 1 }\nfn b() { 2 }\n")], 0);
+        let new = fixture::commit(repo, &[("a.rs", "fn b() { 2 }\nfn a() { 1 }\n")], 60);
+        let revisions = Revisions::pair(repo, &old.to_string(), &new.to_string()).unwrap();
+        let set = processor::commits(fixture::reopen(repo), &revisions, &processor::Options::default());
+        (old, new, set.unwrap())
+    }
+
+    #[test]
+    fn store_and_load() {
+        let repo = fixture::repo("cache_store_and_load");
+        let cache = Cache::new(fixture::directory("cache_store_and_load_cache"));
+        let (old, new, set) = analysed(&repo);
+        assert!(cache.load::<OutputSet>(F, old, new).is_none());
+        cache.store(F, old, new, &set).unwrap();
+        let loaded: OutputSet = cache.load(F, old, new).unwrap();
+        assert_eq!(loaded.old(), old);
+        assert_eq!(loaded.new(), new);
+        assert_eq!(json::encode(&loaded).unwrap(), json::encode(&set).unwrap());
+        // Results for other options are kept apart.
+        assert!(cache.load::<OutputSet>(G, old, new).is_none());
+    }
+
+    #[test]
+    fn entries_prune_and_invalidate() {
+        let repo = fixture::repo("cache_entries_prune_and_invalidate");
+        let cache = Cache::new(fixture::directory("cache_entries_prune_and_invalidate_cache"));
+        let (old, new, set) = analysed(&repo);
+        let gone = Oid::from_str("0123456789abcdef0123456789abcdef01234567").unwrap();
+        cache.store(F, old, new, &set).unwrap();
+        cache.store(F, gone, new, &set).unwrap();
+        cache.store(G, old, new, &set).unwrap();
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|entry| entry.bytes > 0));
+
+        assert_eq!(cache.prune(&repo).unwrap(), 1);
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.old == old.to_string() && entry.new == new.to_string()));
+
+        cache.invalidate(Some(G)).unwrap();
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].fingerprint, F);
+        // Only fingerprints can be cleared, not paths.
+        assert!(cache.invalidate(Some("..")).is_err());
+        assert!(cache.invalidate(Some("/tmp")).is_err());
+        assert_eq!(cache.entries().unwrap().len(), 1);
+
+        cache.invalidate(None).unwrap();
+        assert!(cache.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn fingerprints() {
+        assert!(is_fingerprint("0123456789abcdef"));
+        assert!(!is_fingerprint("0123456789abcde"));
+        assert!(!is_fingerprint("../../0123456789"));
+        assert!(!is_fingerprint("0123456789abcdeg"));
+    }
+}
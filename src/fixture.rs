@@ -0,0 +1,53 @@
+// Small repos built commit by commit for tests, in the system's temp directory.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use git2::{Commit, Oid, Repository, Signature, Time};
+
+/// A fresh, empty directory named after the test.
+pub fn directory(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(&format!("transit-test-{}", name));
+    fs::remove_dir_all(&path).ok();
+    fs::create_dir_all(&path).unwrap();
+    path
+}
+
+/// An empty repo in a fresh directory named after the test.
+pub fn repo(name: &str) -> Repository {
+    Repository::init(&directory(name)).unwrap()
+}
+
+/// Commits a tree of exactly `files`, `(name, contents)` at the root of the
+/// repo, on top of `HEAD`. It is made at `time`, in seconds from Epoch.
+pub fn commit(repo: &Repository, files: &[(&str, &str)], time: i64) -> Oid {
+    let parent = repo.head().ok().and_then(|head| head.target());
+    let parents: Vec<Oid> = parent.into_iter().collect();
+    write(repo, Some("HEAD"), &parents, files, time)
+}
+
+/// Like `commit`, but on top of `parents` and without moving `HEAD`, eg. for
+/// merges and side branches.
+pub fn commit_onto(repo: &Repository, parents: &[Oid], files: &[(&str, &str)], time: i64) -> Oid {
+    write(repo, None, parents, files, time)
+}
+
+fn write(repo: &Repository, update_ref: Option<&str>, parents: &[Oid], files: &[(&str, &str)],
+         time: i64) -> Oid {
+    let mut builder = repo.treebuilder(None).unwrap();
+    for &(name, contents) in files.iter() {
+        let blob = repo.blob(contents.as_bytes()).unwrap();
+        builder.insert(name, blob, 0o100644).unwrap();
+    }
+    let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+    let signature = Signature::new("transit", "transit@example.com", &Time::new(time, 0)).unwrap();
+    let parents: Vec<Commit> = parents.iter().map(|id| repo.find_commit(*id).unwrap()).collect();
+    let parents: Vec<&Commit> = parents.iter().collect();
+    repo.commit(update_ref, &signature, &signature, "Test", &tree, &parents).unwrap()
+}
+
+/// Another handle to `repo`, for the functions which take one by value.
+pub fn reopen(repo: &Repository) -> Repository {
+    Repository::open(repo.path()).unwrap()
+}
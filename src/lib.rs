@@ -0,0 +1,34 @@
+//! Finds code which moved between commits of a git repository.
+//!
+//! `processor::commits` compares two commits, and `processor::RepoWalk` walks
+//! through a repo comparing each commit against its parents. Both are shaped
+//! by `processor::Options`, which holds the `normalizer::Registry` used to
+//! compare lines in each language.
+
+#![feature(core)]
+#![feature(collections)]
+#![feature(slice_patterns)]
+
+#![feature(plugin)]
+#![plugin(regex_macros)] extern crate regex;
+extern crate git2;
+extern crate core;
+extern crate rustc_serialize;
+extern crate num_cpus;
+
+pub use processor::{commits, repo, repo_each, RepoWalk, Options, OutputSet, Output};
+pub use revisions::Revisions;
+pub use normalizer::{KeyNormalizer, Registry};
+pub use scope::Scope;
+pub use cache::Cache;
+
+pub mod processor;
+pub mod scope;
+pub mod normalizer;
+pub mod revisions;
+pub mod pathspec;
+pub mod cache;
+mod similarity;
+mod attributes;
+#[cfg(test)]
+mod fixture;
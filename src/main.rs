@@ -1,48 +1,146 @@
-#![feature(core)]
-#![feature(collections)]
 #![feature(ip_addr)]
-#![feature(slice_patterns)]
+// Without the `cli` feature only a stub `main` is left.
+#![cfg_attr(not(feature = "cli"), allow(dead_code, unused_imports))]
 
-#![feature(plugin)]
-#![plugin(regex_macros)] extern crate regex;
+extern crate transit;
 extern crate git2;
-extern crate core;
 extern crate rustc_serialize;
-extern crate docopt;
-extern crate iron;
-extern crate mount;
+#[cfg(feature = "cli")] extern crate docopt;
+#[cfg(feature = "cli")] extern crate iron;
+#[cfg(feature = "cli")] extern crate mount;
+extern crate num_cpus;
 
-use git2::{Repository, Oid};
-use docopt::Docopt;
+use git2::Repository;
+#[cfg(feature = "cli")] use docopt::Docopt;
 use rustc_serialize::json;
+use std::io::{self, Write};
 use std::path::Path;
+use std::str::FromStr;
 
-mod processor;
-mod web;
-mod scope;
+use transit::{processor, normalizer, revisions};
+use transit::revisions::Revisions;
+use transit::cache::{self, Cache};
+
+#[cfg(feature = "cli")] mod web;
 
 // Write the Docopt usage string.
 static USAGE: &'static str = "
-Usage: transit [--web=<port> | <repo> [<old> <new>] | --help]
+Usage:
+  transit --web=<port>
+  transit cache (list | prune | clear) [--cache-dir=<dir>] <repo> [<fingerprint>]
+  transit [options] [--include=<path>]... [--exclude=<path>]... <repo> [<revision>...]
+  transit --help
 
 Examples:
   transit --web=$PORT       Spawn a web service.
   transit $REPO             Output the results of a revwalk through a repo.
   transit $REPO $ID1 $ID2   Output the data for a pair of commits.
+  transit $REPO v1.0..main  Walk the commits in main since v1.0.
+  transit cache list $REPO  List the cached results for a repo.
   transit --help            Display this message.
 
+Revisions are anything `git rev-parse` understands, eg. branches, tags,
+HEAD~10 or abbreviated IDs, as well as A..B, A...B and ^A. Exactly two plain
+revisions are compared with each other, anything else is walked. --since and
+--until only limit walks.
+
+With --cache, results are kept in .git/transit/ so later runs only analyse
+new commits. `cache prune` removes results for commits which no longer exist,
+and `cache clear` removes all of them, or those of one fingerprint.
+
+Options:
+  --include=<path>          Only analyse files matching <path>, eg. src or
+                            *.rs. May be given more than once.
+  --exclude=<path>          Don't analyse files matching <path>, eg. vendor or
+                            Cargo.lock. May be given more than once.
+  --generated               Also analyse generated files, those marked with
+                            linguist-generated, -diff or binary in
+                            .gitattributes, and files like Cargo.lock.
+  --algorithm=<name>        The diff algorithm, myers, minimal or patience
+                            [default: myers].
+  --context=<n>             Lines of context around each hunk [default: 3].
+  --whitespace=<mode>       Which whitespace changes to ignore: keep (none),
+                            all, change or eol [default: keep].
+  --ignore-blank-lines      Ignore hunks which only add or remove blank lines.
+  --format=<format>         json, or ndjson to write each result on its own
+                            line as soon as it is found [default: json].
+  --jobs=<n>                How many commits to analyse at once when walking a
+                            repo. Defaults to the number of cores.
+  --cache                   Read and write cached results.
+  --cache-dir=<dir>         Where to cache results, .git/transit/ by default.
+                            Implies --cache.
+  --since=<date>            Only walk commits made on or after <date>, either
+                            YYYY-MM-DD or seconds from Epoch.
+  --until=<date>            Only walk commits made on or before <date>.
+  --deep                    Also detect renamed generics, lifetimes and private
+                            fields in moved Rust items.
+  --copies                  Also report added code which duplicates code that
+                            is still in place.
+  --window=<n>              Match code re-added up to <n> commits after it was
+                            deleted when walking a repo.
+  --window-days=<t>         Like --window, but up to <t> days later.
+  --rename-threshold=<p>    How similar, in percent, a file must be to a deleted
+                            one to be treated as renamed [default: 50].
+  --copy-threshold=<p>      How similar, in percent, a file must be to another
+                            to be treated as a copy of it [default: 50].
+  --parents=<mode>          Which parents merges are compared against when
+                            walking a repo: first, every or combined, which
+                            only shows what a merge changed itself
+                            [default: first].
+  --threshold=<t>           How similar, from 0 to 1, a hunk must be to one
+                            that was removed to count as a move [default: 1].
 
-Output is in JSON.
+
+Output is in JSON, or newline delimited JSON with --format=ndjson.
 ";
 
 #[derive(RustcDecodable, Debug)]
 struct Args {
     flag_web: Option<u16>,
+    flag_deep: bool,
+    flag_copies: bool,
+    flag_threshold: f64,
+    flag_window: Option<usize>,
+    flag_window_days: Option<u32>,
+    flag_rename_threshold: u16,
+    flag_copy_threshold: u16,
+    flag_parents: String,
+    flag_include: Vec<String>,
+    flag_exclude: Vec<String>,
+    flag_generated: bool,
+    flag_algorithm: String,
+    flag_context: u32,
+    flag_whitespace: String,
+    flag_ignore_blank_lines: bool,
+    flag_format: String,
+    flag_jobs: Option<usize>,
+    flag_since: Option<String>,
+    flag_until: Option<String>,
+    flag_cache_dir: Option<String>,
+    flag_cache: bool,
+    cmd_cache: bool,
+    cmd_list: bool,
+    cmd_prune: bool,
+    cmd_clear: bool,
+    arg_fingerprint: Option<String>,
     arg_repo: Option<String>,
-    arg_old: Option<String>,
-    arg_new: Option<String>,
+    arg_revision: Vec<String>,
+}
+
+fn open_cache(repo: &Repository, directory: Option<String>) -> Cache {
+    match directory {
+        Some(ref directory) => Cache::new(Path::new(directory).to_path_buf()),
+        None => Cache::for_repo(repo),
+    }
+}
+
+// Exits like docopt does, for arguments it can't check itself.
+#[cfg(feature = "cli")]
+fn invalid(message: String) -> ! {
+    docopt::Error::Argv(message).exit()
 }
 
+#[cfg(feature = "cli")]
 fn main() {
     // Parse the args above or die.
     let args: Args = Docopt::new(USAGE)
@@ -51,21 +149,110 @@ fn main() {
 
     if let Some(port) = args.flag_web {
         web::start(port);
+    } else if args.cmd_cache {
+        let path_string = args.arg_repo.unwrap();
+        let repo = Repository::discover(&Path::new(&path_string))
+            .ok().expect("Unable to find repo.");
+        let cache = open_cache(&repo, args.flag_cache_dir);
+        if args.cmd_list {
+            let entries = cache.entries().unwrap();
+            println!("{}", json::as_pretty_json(&entries).indent(4));
+        } else if args.cmd_prune {
+            println!("Removed {} results.", cache.prune(&repo).unwrap());
+        } else {
+            let fingerprint = args.arg_fingerprint.as_ref().map(|fingerprint| &fingerprint[..]);
+            if let Some(fingerprint) = fingerprint {
+                if !cache::is_fingerprint(fingerprint) {
+                    invalid(format!("`{}` isn't a fingerprint, see `cache list`.", fingerprint));
+                }
+            }
+            cache.invalidate(fingerprint).unwrap();
+        }
     } else if let Some(path_string) = args.arg_repo {
         // Validate values.
+        let streamed = match &args.flag_format[..] {
+            "json" => false,
+            "ndjson" => true,
+            format => invalid(format!("Unknown format `{}`, expected json or ndjson.", format)),
+        };
         let path = Path::new(&path_string);
         let repo = Repository::discover(&path)
             .ok().expect("Unable to find repo.");
-        let old = args.arg_old
-            .and_then(|string| Oid::from_str(&string[..]).ok());
-        let new = args.arg_new
-            .and_then(|string| Oid::from_str(&string[..]).ok());
+        let specs = &args.arg_revision;
+        let plain = specs.iter().all(|revision| !revision.contains("..") && !revision.starts_with("^"));
+        let pair = specs.len() == 2 && plain;
+        if pair && (args.flag_since.is_some() || args.flag_until.is_some()) {
+            invalid(String::from_str("--since and --until can't limit a pair of commits, use A..B to walk them."));
+        }
+        let mut revisions = if pair {
+            Revisions::pair(&repo, &specs[0], &specs[1])
+        } else {
+            Revisions::resolve(&repo, specs)
+        }.ok().expect("Unable to resolve revisions.");
+        revisions.since = args.flag_since
+            .map(|date| revisions::parse_date(&date).unwrap_or_else(|error| invalid(error)));
+        revisions.until = args.flag_until
+            .map(|date| revisions::parse_date(&date).unwrap_or_else(|error| invalid(error)));
+        let mut options = processor::Options::default();
+        if args.flag_deep {
+            options.registry.register(Box::new(normalizer::rust::Rust::deep()));
+        }
+        options.paths.include = args.flag_include;
+        options.paths.exclude = args.flag_exclude;
+        options.generated = args.flag_generated;
+        options.diff.algorithm = processor::Algorithm::from_str(&args.flag_algorithm)
+            .unwrap_or_else(|error| invalid(error));
+        options.diff.context_lines = args.flag_context;
+        options.diff.whitespace = processor::Whitespace::from_str(&args.flag_whitespace)
+            .unwrap_or_else(|error| invalid(error));
+        options.diff.ignore_blank_lines = args.flag_ignore_blank_lines;
+        if let Some(jobs) = args.flag_jobs {
+            options.jobs = jobs;
+        }
+        if args.flag_cache || args.flag_cache_dir.is_some() {
+            options.cache = Some(open_cache(&repo, args.flag_cache_dir));
+        }
+        // Anything at all is similar enough at 0.
+        if !(args.flag_threshold > 0.0 && args.flag_threshold <= 1.0) {
+            invalid(format!("--threshold must be above 0 and at most 1, not {}.", args.flag_threshold));
+        }
+        options.threshold = args.flag_threshold;
+        options.copies = args.flag_copies;
+        if args.flag_rename_threshold > 100 {
+            invalid(format!("--rename-threshold is a percentage, not {}.", args.flag_rename_threshold));
+        }
+        if args.flag_copy_threshold > 100 {
+            invalid(format!("--copy-threshold is a percentage, not {}.", args.flag_copy_threshold));
+        }
+        options.rename_threshold = args.flag_rename_threshold;
+        options.copy_threshold = args.flag_copy_threshold;
+        options.parents = processor::Parents::from_str(&args.flag_parents)
+            .unwrap_or_else(|error| invalid(error));
+        if let Some(commits) = args.flag_window {
+            options.window = Some(processor::Window::Commits(commits));
+        } else if let Some(days) = args.flag_window_days {
+            options.window = Some(processor::Window::Days(days));
+        }
         // Dispatch.
-        if let (Some(old_id), Some(new_id)) = (old, new) {
-            let output = processor::commits(repo, old_id, new_id).unwrap();
-            println!("{}", json::as_pretty_json(&output).indent(4));
+        if pair {
+            let output = processor::commits(repo, &revisions, &options).unwrap();
+            if streamed {
+                println!("{}", json::encode(&output).unwrap());
+            } else {
+                println!("{}", json::as_pretty_json(&output).indent(4));
+            }
+        } else if streamed {
+            let walk = processor::RepoWalk::new(&repo, &revisions, &options).unwrap();
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            for set in walk {
+                let line = json::encode(&set.unwrap()).unwrap();
+                // Whatever was reading went away, eg. `| head`, so the rest
+                // isn't needed.
+                if writeln!(stdout, "{}", line).and_then(|_| stdout.flush()).is_err() { break; }
+            }
         } else {
-            let output = processor::repo(repo).unwrap();
+            let output = processor::repo(repo, &revisions, &options).unwrap();
             println!("{}", json::as_pretty_json(&output).indent(4));
         };
 
@@ -73,3 +260,8 @@ fn main() {
         println!("{}", USAGE);
     }
 }
+
+#[cfg(not(feature = "cli"))]
+fn main() {
+    println!("transit was built without the `cli` feature, only the library is available.");
+}
@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use normalizer::{KeyNormalizer, Lexeme, find_headers, has_extension, matching, scan_string};
+use scope;
+
+/// Renames local declarations in C and C++, and compares preprocessor lines whole.
+pub struct C;
+
+impl KeyNormalizer for C {
+    fn name(&self) -> &str { "c" }
+    fn handles(&self, path: &Path) -> bool {
+        has_extension(path, &["c", "h", "cc", "cpp", "cxx", "hh", "hpp", "hxx"])
+    }
+    fn normalize(&self, hunk: String) -> String { format_key_c(hunk) }
+}
+
+const KEYWORDS: &'static [&'static str] = &[
+    "alignof", "break", "case", "catch", "co_await", "co_return", "co_yield",
+    "const_cast", "continue", "decltype", "default", "delete", "do",
+    "dynamic_cast", "else", "explicit", "false", "final", "for", "friend",
+    "goto", "if", "namespace", "new", "noexcept", "nullptr", "operator",
+    "override", "private", "protected", "public", "reinterpret_cast",
+    "return", "sizeof", "static_assert", "static_cast", "switch", "template",
+    "this", "throw", "true", "try", "typedef", "using", "virtual", "while",
+];
+
+// Keywords which can be part of the type in a declaration.
+const TYPE_KEYWORDS: &'static [&'static str] = &[
+    "auto", "bool", "char", "const", "constexpr", "double", "extern", "float",
+    "inline", "int", "long", "mutable", "register", "restrict", "short",
+    "signed", "static", "unsigned", "void", "volatile",
+    "class", "enum", "struct", "typename", "union",
+];
+
+// `<` and `>` are never joined so that template arguments can be matched.
+const PUNCTUATION: &'static [&'static str] = &[
+    "...", "<<=", ">>=", "->*", "->", "::", "++", "--", "==", "!=", "<=",
+    ">=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", ".*", "##",
+];
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().chain(TYPE_KEYWORDS.iter()).any(|keyword| *keyword == word)
+}
+
+fn is_type_keyword(word: &str) -> bool {
+    TYPE_KEYWORDS.iter().any(|keyword| *keyword == word)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Kind {
+    Ident, Literal, Punct, Directive
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    text: String,
+}
+
+impl Lexeme for Token {
+    fn is(&self, text: &str) -> bool {
+        (self.kind == Kind::Punct || self.kind == Kind::Ident) && self.text == text
+    }
+}
+
+// `R"delim( ... )delim"`, `start` is the index of the opening quote.
+fn scan_raw_string(chars: &[char], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() && chars[i] != '(' { i += 1; }
+    let mut terminator = vec![')'];
+    terminator.extend(chars[start + 1..i].iter().cloned());
+    terminator.push('"');
+    while i < chars.len() {
+        if chars[i..].starts_with(&terminator) {
+            return i + terminator.len();
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+fn tokenize_c(hunk: &str) -> Vec<Token> {
+    let chars: Vec<char> = hunk.chars().collect();
+    let mut tokens = Vec::new();
+    let mut at_line_start = true;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind;
+
+        if c == '\n' {
+            at_line_start = true;
+            i += 1;
+            continue;
+        } else if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '/' {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+            continue;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '*' {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && i + 1 < chars.len() && chars[i + 1] == '/') { i += 1; }
+            i += 2;
+            continue;
+        } else if c == '#' && at_line_start {
+            // Preprocessor lines are a single token, including `\` continuations.
+            while i < chars.len() && !(chars[i] == '\n' && chars[i - 1] != '\\') { i += 1; }
+            let line: String = chars[start..i].iter().cloned().collect();
+            let code = match line.find("//") {
+                Some(comment) => &line[..comment],
+                None => &line[..],
+            };
+            let words: Vec<&str> = code.split(|c: char| c.is_whitespace() || c == '\\')
+                .filter(|word| !word.is_empty())
+                .collect();
+            tokens.push(Token { kind: Kind::Directive, text: words.connect(" ") });
+            continue;
+        } else if c == '"' || c == '\'' {
+            i = scan_string(&chars, i);
+            kind = Kind::Literal;
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') { i += 1; }
+            // String and character prefixes, eg. `L"..."`, `u8"..."` and `R"(...)"`.
+            let word: String = chars[start..i].iter().cloned().collect();
+            let prefix = word == "L" || word == "u" || word == "U" || word == "u8" || word.ends_with("R");
+            if prefix && i < chars.len() && (chars[i] == '"' || chars[i] == '\'') {
+                i = if word.ends_with("R") { scan_raw_string(&chars, i) } else { scan_string(&chars, i) };
+                kind = Kind::Literal;
+            } else {
+                kind = Kind::Ident;
+            }
+        } else if c.is_digit(10) {
+            // Includes C++14 digit separators, `1'000`.
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.' ||
+                                      (chars[i] == '\'' && i + 1 < chars.len() && chars[i + 1].is_digit(10))) {
+                i += 1;
+            }
+            kind = Kind::Literal;
+        } else {
+            let rest: String = chars[i..].iter().take(3).cloned().collect();
+            i += match PUNCTUATION.iter().find(|punct| rest.starts_with(**punct)) {
+                Some(punct) => punct.len(),
+                None => 1,
+            };
+            kind = Kind::Punct;
+        }
+
+        at_line_start = false;
+        tokens.push(Token {
+            kind: kind,
+            text: chars[start..i].iter().cloned().collect(),
+        });
+    }
+
+    tokens
+}
+
+// Walks backwards from a name over what could be its type. Returns whether it
+// was a type, and the index of the token before the type.
+fn read_type_backwards(tokens: &[Token], name: usize) -> Option<Option<usize>> {
+    let mut index = name;
+    let mut saw_type = false;
+    let mut elaborated_only = true;
+    loop {
+        if index == 0 { break; }
+        let token = &tokens[index - 1];
+        match token.kind {
+            Kind::Ident => {
+                if is_keyword(&token.text) && !is_type_keyword(&token.text) { return None; }
+                let elaborated = token.is("struct") || token.is("class") || token.is("union") || token.is("enum");
+                if !elaborated { elaborated_only = false; }
+                saw_type = true;
+                index -= 1;
+            },
+            Kind::Punct if token.is("*") || token.is("&") || token.is("&&") || token.is("::") => {
+                index -= 1;
+            },
+            Kind::Punct if token.is(">") => {
+                // Template arguments, `std::vector<int> values`.
+                let mut depth = 0;
+                loop {
+                    let token = &tokens[index - 1];
+                    if token.is(">") { depth += 1; }
+                    if token.is("<") { depth -= 1; }
+                    if token.is(";") || token.is("{") || token.is("}") { return None; }
+                    index -= 1;
+                    if depth == 0 { break; }
+                    if index == 0 { return None; }
+                }
+            },
+            _ => break,
+        }
+    }
+    if !saw_type || elaborated_only { return None; }
+    if index == 0 { return Some(None); }
+    let before = &tokens[index - 1];
+    let boundary = before.kind == Kind::Directive || before.is(";") || before.is("{") ||
+        before.is("}") || before.is("(") || before.is(",") || before.is(":");
+    if boundary { Some(Some(index - 1)) } else { None }
+}
+
+// Finds the indices of declared names.
+fn find_declarations(tokens: &[Token]) -> HashSet<usize> {
+    let mut declarations = HashSet::new();
+
+    for index in 0..tokens.len() {
+        let token = &tokens[index];
+        if token.kind != Kind::Ident || is_keyword(&token.text) { continue; }
+        let declarator_end = match tokens.get(index + 1) {
+            None => true,
+            Some(next) => next.is("=") || next.is(";") || next.is(",") || next.is("[") ||
+                next.is(":") || next.is(")") || next.is("{"),
+        };
+        if !declarator_end { continue; }
+        let before = match read_type_backwards(tokens, index) {
+            Some(before) => before,
+            None => continue,
+        };
+        declarations.insert(index);
+
+        // `int a = 1, *b, c[2];` outside of parameter lists.
+        let in_parameters = match before {
+            Some(before) => tokens[before].is("(") || tokens[before].is(","),
+            None => false,
+        };
+        if in_parameters { continue; }
+        let mut next = index + 1;
+        while next < tokens.len() {
+            let token = &tokens[next];
+            if token.is(";") || token.is(")") || token.is("}") {
+                break;
+            } else if token.is("(") || token.is("[") || token.is("{") {
+                next = matching(tokens, next) + 1;
+            } else if token.is(",") {
+                next += 1;
+                while next < tokens.len() && (tokens[next].is("*") || tokens[next].is("&")) { next += 1; }
+                if next < tokens.len() && tokens[next].kind == Kind::Ident && !is_keyword(&tokens[next].text) {
+                    declarations.insert(next);
+                }
+            } else {
+                next += 1;
+            }
+        }
+    }
+
+    declarations
+}
+
+fn format_key_c(original_string: String) -> String {
+    let tokens = tokenize_c(&original_string);
+    let declarations = find_declarations(&tokens);
+    // `int get() const {`
+    let headers = find_headers(&tokens, &["{"], |tokens, mut next| {
+        while next < tokens.len() && (tokens[next].is("const") || tokens[next].is("noexcept") ||
+                                      tokens[next].is("override")) {
+            next += 1;
+        }
+        next
+    });
+
+    let mut scope = scope::Scope::new();
+    let mut key = String::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 { key.push(' '); }
+        match token.kind {
+            Kind::Ident => {
+                if declarations.contains(&index) {
+                    scope.add_variable(token.text.clone());
+                }
+                // Members and qualified names, `a.b`, `a->b` and `a::b`, are never renamed.
+                let member = index > 0 && (tokens[index - 1].is(".") || tokens[index - 1].is("->") ||
+                                           tokens[index - 1].is("::")) ||
+                    (index + 1 < tokens.len() && tokens[index + 1].is("::"));
+                match scope.get_variable(token.text.clone()) {
+                    Some(ref replacement) if !member => key.push_str(replacement),
+                    _ => key.push_str(&token.text),
+                }
+            },
+            Kind::Punct => {
+                if token.is("{") || (token.is("(") && headers.contains(&index)) {
+                    scope.increase_depth();
+                }
+                if token.is("}") || (token.is(")") && headers.contains(&index)) {
+                    scope.decrease_depth();
+                }
+                key.push_str(&token.text);
+            },
+            Kind::Literal | Kind::Directive => key.push_str(&token.text),
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_key_c;
+
+    #[test]
+    fn renamed_locals_match() {
+        let old = "// Sum.\nint total = 0;\nfor (size_t i = 0; i < n; i++) {\n    total += values[i];\n}\n";
+        let new = "int sum = 0; /* Sum. */\nfor (size_t j = 0; j < n; j++) {\n    sum += values[j];\n}\n";
+        assert_eq!(format_key_c(String::from_str(old)), format_key_c(String::from_str(new)));
+    }
+
+    #[test]
+    fn preprocessor_lines_are_tokens() {
+        let old = "#include <stdio.h>\n#define MAX 10\n";
+        let new = "#include  <stdio.h>\n#define MAX  10 // Limit.\n";
+        assert_eq!(format_key_c(String::from_str(old)), format_key_c(String::from_str(new)));
+    }
+
+    #[test]
+    fn expressions_are_not_declarations() {
+        let old = "x = a * b;\n";
+        let new = "x = a * c;\n";
+        assert!(format_key_c(String::from_str(old)) != format_key_c(String::from_str(new)));
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use normalizer::{KeyNormalizer, Lexeme, has_extension, matching, scan_string, scan_until};
+use scope;
+
+/// Renames `:=` and `var` declarations, parameters and method receivers in Go.
+pub struct Go;
+
+impl KeyNormalizer for Go {
+    fn name(&self) -> &str { "go" }
+    fn handles(&self, path: &Path) -> bool { has_extension(path, &["go"]) }
+    fn normalize(&self, hunk: String) -> String { format_key_go(hunk) }
+}
+
+const KEYWORDS: &'static [&'static str] = &[
+    "break", "case", "chan", "const", "continue", "default", "defer", "else",
+    "fallthrough", "for", "func", "go", "goto", "if", "import", "interface",
+    "map", "package", "range", "return", "select", "struct", "switch", "type", "var",
+];
+
+const PUNCTUATION: &'static [&'static str] = &[
+    "<<=", ">>=", "&^=", "...", ":=", "<-", "&&", "||", "==", "!=", "<=", ">=",
+    "<<", ">>", "&^", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+];
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|keyword| *keyword == word)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Kind {
+    Ident, Literal, Punct
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    text: String,
+    newline_before: bool,
+}
+
+impl Lexeme for Token {
+    fn is(&self, text: &str) -> bool {
+        self.kind != Kind::Literal && self.text == text
+    }
+}
+
+impl Token {
+    fn is_name(&self) -> bool {
+        self.kind == Kind::Ident && !is_keyword(&self.text)
+    }
+}
+
+// Returns the index just past the end of the literal starting at `start`. Raw
+// strings, quoted with backticks, have no escapes and may span lines.
+fn scan_literal(chars: &[char], start: usize) -> usize {
+    if chars[start] == '`' {
+        scan_until(chars, start + 1, &['`'], false, true)
+    } else {
+        scan_string(chars, start)
+    }
+}
+
+fn tokenize_go(hunk: &str) -> Vec<Token> {
+    let chars: Vec<char> = hunk.chars().collect();
+    let mut tokens = Vec::new();
+    let mut newline = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind;
+
+        if c == '\n' {
+            newline = true;
+            i += 1;
+            continue;
+        } else if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '/' {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+            continue;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '*' {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && i + 1 < chars.len() && chars[i + 1] == '/') {
+                if chars[i] == '\n' { newline = true; }
+                i += 1;
+            }
+            i += 2;
+            continue;
+        } else if c == '"' || c == '\'' || c == '`' {
+            i = scan_literal(&chars, i);
+            kind = Kind::Literal;
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') { i += 1; }
+            kind = Kind::Ident;
+        } else if c.is_digit(10) {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') { i += 1; }
+            kind = Kind::Literal;
+        } else {
+            let rest: String = chars[i..].iter().take(3).cloned().collect();
+            i += match PUNCTUATION.iter().find(|punct| rest.starts_with(**punct)) {
+                Some(punct) => punct.len(),
+                None => 1,
+            };
+            kind = Kind::Punct;
+        }
+
+        tokens.push(Token {
+            kind: kind,
+            text: chars[start..i].iter().cloned().collect(),
+            newline_before: newline,
+        });
+        newline = false;
+    }
+
+    tokens
+}
+
+// Reads `a, b, c` starting at `start`, recording each name.
+fn read_names(tokens: &[Token], start: usize, bindings: &mut HashMap<usize, i64>, offset: i64) {
+    let mut index = start;
+    while index < tokens.len() && tokens[index].is_name() {
+        bindings.insert(index, offset);
+        if index + 1 < tokens.len() && tokens[index + 1].is(",") {
+            index += 2;
+        } else {
+            break;
+        }
+    }
+}
+
+// Reads a parameter list from the `(` at `open`. Go lists may be all types,
+// `(int, error)`, or names with types, `(a, b int, c string)`.
+fn read_parameters(tokens: &[Token], open: usize, bindings: &mut HashMap<usize, i64>) -> usize {
+    let close = matching(tokens, open);
+    let mut parts = Vec::new();
+    let mut start = open + 1;
+    let mut index = open + 1;
+    while index < close {
+        if tokens[index].is("(") || tokens[index].is("[") || tokens[index].is("{") {
+            index = matching(tokens, index) + 1;
+            continue;
+        }
+        if tokens[index].is(",") {
+            parts.push((start, index));
+            start = index + 1;
+        }
+        index += 1;
+    }
+    if start < close { parts.push((start, close)); }
+
+    let named = parts.iter().any(|&(start, end)| {
+        end - start >= 2 && tokens[start].is_name() && !tokens[start + 1].is(".")
+    });
+    if named {
+        for &(start, _) in parts.iter() {
+            if tokens[start].is_name() { bindings.insert(start, 1); }
+        }
+    }
+    close
+}
+
+// Maps the index of each binding name to the scope depth offset it lives at.
+// Receivers, parameters and named results are bound inside of the body.
+fn find_bindings(tokens: &[Token]) -> HashMap<usize, i64> {
+    let mut bindings = HashMap::new();
+
+    for index in 0..tokens.len() {
+        let token = &tokens[index];
+        if token.is(":=") {
+            // `a, b := ...`, which includes `for k, v := range ...`.
+            let mut name = index;
+            while name > 0 && tokens[name - 1].is_name() {
+                bindings.insert(name - 1, 0);
+                if name >= 2 && tokens[name - 2].is(",") {
+                    name -= 2;
+                } else {
+                    break;
+                }
+            }
+        } else if token.is("var") || token.is("const") {
+            if index + 1 < tokens.len() && tokens[index + 1].is("(") {
+                // Grouped declarations, one per line.
+                let close = matching(tokens, index + 1);
+                for line in index + 2..close {
+                    if line == index + 2 || tokens[line].newline_before {
+                        read_names(tokens, line, &mut bindings, 0);
+                    }
+                }
+            } else {
+                read_names(tokens, index + 1, &mut bindings, 0);
+            }
+        } else if token.is("func") {
+            let mut next = index + 1;
+            // Method receiver, `func (s *Server) ...`.
+            if next < tokens.len() && tokens[next].is("(") {
+                next = read_parameters(tokens, next, &mut bindings) + 1;
+            }
+            if next < tokens.len() && tokens[next].kind == Kind::Ident {
+                next += 1;
+            }
+            if next < tokens.len() && tokens[next].is("(") {
+                next = read_parameters(tokens, next, &mut bindings) + 1;
+            }
+            // Named results.
+            if next < tokens.len() && tokens[next].is("(") {
+                read_parameters(tokens, next, &mut bindings);
+            }
+        }
+    }
+
+    bindings
+}
+
+fn format_key_go(original_string: String) -> String {
+    let tokens = tokenize_go(&original_string);
+    let bindings = find_bindings(&tokens);
+
+    let mut scope = scope::Scope::new();
+    let mut key = String::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 { key.push(' '); }
+        match token.kind {
+            Kind::Punct => {
+                if token.is("{") { scope.increase_depth(); }
+                if token.is("}") { scope.decrease_depth(); }
+                key.push_str(&token.text);
+            },
+            Kind::Literal => key.push_str(&token.text),
+            Kind::Ident => {
+                if let Some(offset) = bindings.get(&index) {
+                    for _ in 0..*offset { scope.increase_depth(); }
+                    // `:=` may reassign a name which is already in scope.
+                    if *offset > 0 || scope.get_variable(token.text.clone()).is_none() {
+                        scope.add_variable(token.text.clone());
+                    }
+                    let replacement = scope.get_variable(token.text.clone()).unwrap();
+                    for _ in 0..*offset { scope.decrease_depth(); }
+                    key.push_str(&replacement);
+                    continue;
+                }
+                // Selectors, `a.b`, and composite literal keys, `T{b: 1}`, are never renamed.
+                let field = index > 0 && tokens[index - 1].is(".") ||
+                    (index + 1 < tokens.len() && tokens[index + 1].is(":") &&
+                     index > 0 && (tokens[index - 1].is("{") || tokens[index - 1].is(",")));
+                match scope.get_variable(token.text.clone()) {
+                    Some(ref replacement) if !field => key.push_str(replacement),
+                    _ => key.push_str(&token.text),
+                }
+            },
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_key_go;
+
+    #[test]
+    fn renamed_range_variables_match() {
+        let old = "for i, item := range items {\n\ttotal += item.Size // Bytes.\n}\n";
+        let new = "for j, it := range items {\n\ttotal += it.Size\n}\n";
+        assert_eq!(format_key_go(String::from_str(old)), format_key_go(String::from_str(new)));
+    }
+
+    #[test]
+    fn renamed_receivers_match() {
+        let old = "func (s *Server) Close(force bool) error {\n\treturn s.stop(force)\n}\n";
+        let new = "func (srv *Server) Close(f bool) error {\n\treturn srv.stop(f)\n}\n";
+        assert_eq!(format_key_go(String::from_str(old)), format_key_go(String::from_str(new)));
+    }
+}
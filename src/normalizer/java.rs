@@ -0,0 +1,315 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use normalizer::{KeyNormalizer, Lexeme, find_headers, has_extension, matching, scan_string, scan_until};
+use scope;
+
+/// Renames local variables, enhanced `for` variables, lambda and catch parameters in Java.
+pub struct Java;
+
+impl KeyNormalizer for Java {
+    fn name(&self) -> &str { "java" }
+    fn handles(&self, path: &Path) -> bool { has_extension(path, &["java"]) }
+    fn normalize(&self, hunk: String) -> String { format_key_java(hunk) }
+}
+
+const KEYWORDS: &'static [&'static str] = &[
+    "abstract", "assert", "break", "case", "catch", "class", "continue",
+    "default", "do", "else", "enum", "extends", "false", "finally", "for",
+    "if", "implements", "import", "instanceof", "interface", "native", "new",
+    "null", "package", "private", "protected", "public", "return", "static",
+    "strictfp", "super", "switch", "synchronized", "this", "throw", "throws",
+    "transient", "true", "try", "volatile", "while", "yield",
+];
+
+// Keywords which can be part of the type in a local declaration.
+const TYPE_KEYWORDS: &'static [&'static str] = &[
+    "boolean", "byte", "char", "double", "final", "float", "int", "long",
+    "short", "var", "void",
+];
+
+const PUNCTUATION: &'static [&'static str] = &[
+    ">>>=", "<<=", ">>=", "...", "->", "::", "++", "--", "&&", "||", "==",
+    "!=", "<=", ">=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+];
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().chain(TYPE_KEYWORDS.iter()).any(|keyword| *keyword == word)
+}
+
+fn is_type_keyword(word: &str) -> bool {
+    TYPE_KEYWORDS.iter().any(|keyword| *keyword == word)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Kind {
+    Ident, Literal, Punct
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    text: String,
+}
+
+impl Lexeme for Token {
+    fn is(&self, text: &str) -> bool {
+        self.kind != Kind::Literal && self.text == text
+    }
+}
+
+impl Token {
+    fn is_name(&self) -> bool {
+        self.kind == Kind::Ident && !is_keyword(&self.text)
+    }
+}
+
+// Whether a line holds nothing but an annotation, eg. `@Override` or `@SuppressWarnings("unchecked")`.
+fn is_annotation_line(line: &str) -> bool {
+    let trimmed = line.trim();
+    if !trimmed.starts_with("@") || trimmed.starts_with("@interface") { return false; }
+    let mut depth = 0;
+    let mut rest = "";
+    for (index, c) in trimmed.char_indices().skip(1) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ if depth == 0 && !(c.is_alphanumeric() || c == '_' || c == '.') => {
+                rest = &trimmed[index..];
+                break;
+            },
+            _ => (),
+        }
+    }
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with("//")
+}
+
+// Returns the index just past the end of the literal starting at `start`. Text
+// blocks, `"""`, may span lines.
+fn scan_literal(chars: &[char], start: usize) -> usize {
+    let block = ['"', '"', '"'];
+    if chars[start..].starts_with(&block) {
+        scan_until(chars, start + 3, &block, true, true)
+    } else {
+        scan_string(chars, start)
+    }
+}
+
+fn tokenize_java(hunk: &str) -> Vec<Token> {
+    let code: Vec<&str> = hunk.lines().filter(|line| !is_annotation_line(line)).collect();
+    let chars: Vec<char> = code.connect("\n").chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '/' {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+            continue;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '*' {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && i + 1 < chars.len() && chars[i + 1] == '/') { i += 1; }
+            i += 2;
+            continue;
+        } else if c == '"' || c == '\'' {
+            i = scan_literal(&chars, i);
+            kind = Kind::Literal;
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') { i += 1; }
+            kind = Kind::Ident;
+        } else if c.is_digit(10) {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') { i += 1; }
+            kind = Kind::Literal;
+        } else {
+            let rest: String = chars[i..].iter().take(4).cloned().collect();
+            i += match PUNCTUATION.iter().find(|punct| rest.starts_with(**punct)) {
+                Some(punct) => punct.len(),
+                None => 1,
+            };
+            kind = Kind::Punct;
+        }
+
+        tokens.push(Token {
+            kind: kind,
+            text: chars[start..i].iter().cloned().collect(),
+        });
+    }
+
+    tokens
+}
+
+// Whether the tokens before `name` are a type, `final List<String>[] name`, at
+// the start of a statement or parameter.
+fn follows_type(tokens: &[Token], name: usize) -> bool {
+    // A type is always apart from the name, so `obj.count` is a field.
+    if name > 0 && tokens[name - 1].is(".") { return false; }
+    let mut index = name;
+    let mut saw_type = false;
+    loop {
+        if index == 0 { return saw_type; }
+        let token = &tokens[index - 1];
+        match token.kind {
+            Kind::Ident => {
+                if is_keyword(&token.text) && !is_type_keyword(&token.text) { return false; }
+                saw_type = true;
+                index -= 1;
+            },
+            Kind::Punct if token.is(".") || token.is("[") || token.is("]") || token.is("...") => {
+                index -= 1;
+            },
+            Kind::Punct if token.is(">") => {
+                // Type arguments, `Map<String, List<Integer>> counts`.
+                let mut depth = 0;
+                loop {
+                    let token = &tokens[index - 1];
+                    if token.is(">") { depth += 1; }
+                    if token.is("<") { depth -= 1; }
+                    if token.is(";") || token.is("{") || token.is("}") { return false; }
+                    index -= 1;
+                    if depth == 0 { break; }
+                    if index == 0 { return false; }
+                }
+            },
+            _ => {
+                return saw_type && (token.is(";") || token.is("{") || token.is("}") ||
+                                    token.is("(") || token.is(",") || token.is(")") && is_annotation_end(tokens, index - 1));
+            },
+        }
+    }
+}
+
+// Whether the `)` at `close` ends an inline annotation, `@Named("x") String name`.
+fn is_annotation_end(tokens: &[Token], close: usize) -> bool {
+    let mut depth = 0;
+    let mut index = close;
+    loop {
+        if tokens[index].is(")") { depth += 1; }
+        if tokens[index].is("(") { depth -= 1; }
+        if depth == 0 || index == 0 { break; }
+        index -= 1;
+    }
+    index >= 2 && tokens[index - 1].kind == Kind::Ident && tokens[index - 2].is("@")
+}
+
+// Finds the indices of declared names.
+fn find_declarations(tokens: &[Token]) -> HashSet<usize> {
+    let mut declarations = HashSet::new();
+
+    for index in 0..tokens.len() {
+        let token = &tokens[index];
+
+        if token.is("->") && index > 0 {
+            // Lambda parameters, `x -> ...` and `(a, b) -> ...`.
+            if tokens[index - 1].is_name() {
+                declarations.insert(index - 1);
+            } else if tokens[index - 1].is(")") {
+                let mut open = index - 1;
+                let mut depth = 0;
+                loop {
+                    if tokens[open].is(")") { depth += 1; }
+                    if tokens[open].is("(") { depth -= 1; }
+                    if depth == 0 || open == 0 { break; }
+                    open -= 1;
+                }
+                // Only the untyped form, typed parameters are found below.
+                for param in open + 1..index - 1 {
+                    let untyped = tokens[param - 1].is("(") || tokens[param - 1].is(",");
+                    if tokens[param].is_name() && untyped &&
+                        (tokens[param + 1].is(",") || tokens[param + 1].is(")")) {
+                        declarations.insert(param);
+                    }
+                }
+            }
+            continue;
+        }
+
+        if !token.is_name() { continue; }
+        let declarator_end = match tokens.get(index + 1) {
+            None => true,
+            // `:` for enhanced `for`, `)` and `,` for parameters, including `catch (E e)`.
+            Some(next) => next.is("=") || next.is(";") || next.is(",") || next.is(":") || next.is(")"),
+        };
+        if declarator_end && follows_type(tokens, index) {
+            declarations.insert(index);
+        }
+    }
+
+    declarations
+}
+
+fn format_key_java(original_string: String) -> String {
+    let tokens = tokenize_java(&original_string);
+    let declarations = find_declarations(&tokens);
+    // `void run() throws IOException, InterruptedException {`
+    let headers = find_headers(&tokens, &["{", "->"], |tokens, mut next| {
+        if next < tokens.len() && tokens[next].is("throws") {
+            while next < tokens.len() && !tokens[next].is("{") && !tokens[next].is(";") { next += 1; }
+        }
+        next
+    });
+
+    let mut scope = scope::Scope::new();
+    let mut key = String::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 { key.push(' '); }
+        match token.kind {
+            Kind::Ident => {
+                if declarations.contains(&index) {
+                    scope.add_variable(token.text.clone());
+                }
+                // Members, `a.b` and `A::b`, are never renamed.
+                let member = index > 0 && (tokens[index - 1].is(".") || tokens[index - 1].is("::"));
+                match scope.get_variable(token.text.clone()) {
+                    Some(ref replacement) if !member => key.push_str(replacement),
+                    _ => key.push_str(&token.text),
+                }
+            },
+            Kind::Punct => {
+                if token.is("{") || (token.is("(") && headers.contains(&index)) {
+                    scope.increase_depth();
+                }
+                if token.is("}") || (token.is(")") && headers.contains(&index)) {
+                    scope.decrease_depth();
+                }
+                key.push_str(&token.text);
+            },
+            Kind::Literal => key.push_str(&token.text),
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_key_java;
+
+    #[test]
+    fn renamed_locals_match() {
+        let old = "@Override\npublic int size() {\n    int count = 0;\n    for (Item item : items) { count += item.size(); }\n    return count;\n}\n";
+        let new = "public int size() {\n    int total = 0; // Sum.\n    for (Item i : items) { total += i.size(); }\n    return total;\n}\n";
+        assert_eq!(format_key_java(String::from_str(old)), format_key_java(String::from_str(new)));
+    }
+
+    #[test]
+    fn renamed_lambda_and_catch_parameters_match() {
+        let old = "try { items.forEach(x -> use(x)); } catch (IOException e) { log(e); }\n";
+        let new = "try { items.forEach(it -> use(it)); } catch (IOException err) { log(err); }\n";
+        assert_eq!(format_key_java(String::from_str(old)), format_key_java(String::from_str(new)));
+    }
+
+    #[test]
+    fn fields_are_not_declared() {
+        let old = "obj.count = 0;\n";
+        let new = "obj.total = 0;\n";
+        assert!(format_key_java(String::from_str(old)) != format_key_java(String::from_str(new)));
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use normalizer::{KeyNormalizer, Lexeme, has_extension, matching, scan_string};
+use scope;
+
+/// Renames `var`/`let`/`const` bindings and parameters in JavaScript and TypeScript.
+pub struct JavaScript;
+
+impl KeyNormalizer for JavaScript {
+    fn name(&self) -> &str { "javascript" }
+    fn handles(&self, path: &Path) -> bool {
+        has_extension(path, &["js", "jsx", "mjs", "cjs", "ts", "tsx"])
+    }
+    fn normalize(&self, hunk: String) -> String { format_key_javascript(hunk) }
+}
+
+const PUNCTUATION: &'static [&'static str] = &[
+    ">>>=", "...", "===", "!==", "**=", "<<=", ">>=", ">>>",
+    "=>", "==", "!=", "<=", ">=", "&&", "||", "??", "?.", "++", "--",
+    "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "**", "<<", ">>",
+];
+
+// Keywords after which a `/` starts a regex literal rather than a division.
+const REGEX_PREFIXES: &'static [&'static str] = &[
+    "return", "typeof", "instanceof", "in", "of", "new", "delete", "void",
+    "throw", "case", "do", "else", "yield", "await",
+];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Kind {
+    Ident, Literal, Punct
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: Kind,
+    text: String,
+    newline_before: bool,
+}
+
+impl Lexeme for Token {
+    fn is(&self, text: &str) -> bool {
+        self.kind != Kind::Literal && self.text == text
+    }
+}
+
+fn regex_allowed(previous: Option<&Token>) -> bool {
+    match previous {
+        None => true,
+        Some(token) => match token.kind {
+            Kind::Punct => !(token.is(")") || token.is("]") || token.is("}")),
+            Kind::Ident => REGEX_PREFIXES.iter().any(|word| token.is(word)),
+            Kind::Literal => false,
+        },
+    }
+}
+
+// Returns the index just past a template literal chunk, which ends at either a
+// closing backtick or the `${` of an embedded expression, and whether it was `${`.
+fn scan_template(chars: &[char], start: usize) -> (usize, bool) {
+    let mut i = start;
+    while i < chars.len() {
+        if chars[i] == '\\' {
+            i += 2;
+        } else if chars[i] == '`' {
+            return (i + 1, false);
+        } else if chars[i] == '$' && i + 1 < chars.len() && chars[i + 1] == '{' {
+            return (i + 2, true);
+        } else {
+            i += 1;
+        }
+    }
+    (chars.len(), false)
+}
+
+fn scan_regex(chars: &[char], start: usize) -> usize {
+    let mut i = start + 1;
+    let mut in_class = false;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => {
+                i += 1;
+                while i < chars.len() && chars[i].is_alphabetic() { i += 1; }
+                return i;
+            },
+            '\n' => return i,
+            _ => (),
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+fn tokenize_javascript(hunk: &str) -> Vec<Token> {
+    let chars: Vec<char> = hunk.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut newline = false;
+    // Brace nesting, and the nesting at which each open `${` of a template resumes.
+    let mut braces = 0;
+    let mut templates: Vec<i64> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let kind;
+
+        if c == '\n' {
+            newline = true;
+            i += 1;
+            continue;
+        } else if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '/' {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+            continue;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '*' {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && i + 1 < chars.len() && chars[i + 1] == '/') {
+                if chars[i] == '\n' { newline = true; }
+                i += 1;
+            }
+            i += 2;
+            continue;
+        } else if c == '"' || c == '\'' {
+            i = scan_string(&chars, i);
+            kind = Kind::Literal;
+        } else if c == '`' || (c == '}' && templates.last() == Some(&braces)) {
+            if c == '}' { templates.pop(); }
+            let (end, embedded) = scan_template(&chars, i + 1);
+            if embedded { templates.push(braces); }
+            i = end;
+            kind = Kind::Literal;
+        } else if c == '/' && regex_allowed(tokens.last()) {
+            i = scan_regex(&chars, i);
+            kind = Kind::Literal;
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') { i += 1; }
+            kind = Kind::Ident;
+        } else if c.is_digit(10) {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') { i += 1; }
+            kind = Kind::Literal;
+        } else {
+            let rest: String = chars[i..].iter().take(4).cloned().collect();
+            i += match PUNCTUATION.iter().find(|punct| rest.starts_with(**punct)) {
+                Some(punct) => punct.len(),
+                None => 1,
+            };
+            match c {
+                '{' => braces += 1,
+                '}' => braces -= 1,
+                _ => (),
+            }
+            kind = Kind::Punct;
+        }
+
+        tokens.push(Token {
+            kind: kind,
+            text: chars[start..i].iter().cloned().collect(),
+            newline_before: newline,
+        });
+        newline = false;
+    }
+
+    tokens
+}
+
+// Skips an expression, stopping at a top level `,`, `;` or closing bracket, or
+// where automatic semicolon insertion would end the statement.
+fn skip_expression(tokens: &[Token], start: usize) -> usize {
+    let mut index = start;
+    while index < tokens.len() {
+        let token = &tokens[index];
+        if token.is("(") || token.is("[") || token.is("{") {
+            index = matching(tokens, index) + 1;
+            continue;
+        }
+        if token.is(",") || token.is(";") || token.is(")") || token.is("]") || token.is("}") {
+            return index;
+        }
+        if index > start && token.newline_before {
+            let previous = &tokens[index - 1];
+            let complete = previous.kind != Kind::Punct || previous.is(")") || previous.is("]") || previous.is("}");
+            if complete && token.kind != Kind::Punct { return index; }
+        }
+        index += 1;
+    }
+    index
+}
+
+// Reads a binding pattern (a name, or an object or array destructuring) and
+// records the indices of the names it binds. Returns the index after it.
+fn read_pattern(tokens: &[Token], start: usize, targets: &mut Vec<usize>) -> usize {
+    if start >= tokens.len() { return start; }
+    let token = &tokens[start];
+    if token.kind == Kind::Ident {
+        targets.push(start);
+        return start + 1;
+    }
+    if !(token.is("{") || token.is("[")) { return start; }
+
+    let object = token.is("{");
+    let end = matching(tokens, start);
+    let mut index = start + 1;
+    while index < end {
+        let token = &tokens[index];
+        if token.is(",") {
+            index += 1;
+        } else if token.is("...") {
+            index = read_pattern(tokens, index + 1, targets);
+        } else if object && index + 1 < end && tokens[index + 1].is(":") {
+            // `{ key: target }`, the key is a property name.
+            index = read_pattern(tokens, index + 2, targets);
+        } else if object && token.is("[") {
+            // `{ [computed]: target }`
+            index = matching(tokens, index) + 1;
+            if index < end && tokens[index].is(":") {
+                index = read_pattern(tokens, index + 1, targets);
+            }
+        } else {
+            let next = read_pattern(tokens, index, targets);
+            index = if next == index { index + 1 } else { next };
+        }
+        // Default values, `{ a = 1 }` or `[b = 2]`.
+        if index < end && tokens[index].is("=") {
+            index = skip_expression(tokens, index + 1);
+        }
+    }
+    end + 1
+}
+
+// Reads a parameter list from the `(` at `open`, recording bound names.
+fn read_parameters(tokens: &[Token], open: usize, targets: &mut Vec<usize>) -> usize {
+    let close = matching(tokens, open);
+    let mut index = open + 1;
+    while index < close {
+        if tokens[index].is(",") || tokens[index].is("...") {
+            index += 1;
+            continue;
+        }
+        let next = read_pattern(tokens, index, targets);
+        index = if next == index { index + 1 } else { next };
+        // TypeScript `?`, `: Type` and default values.
+        if index < close && tokens[index].is("?") { index += 1; }
+        if index < close && (tokens[index].is(":") || tokens[index].is("=")) {
+            index = skip_expression(tokens, index + 1);
+            if index < close && tokens[index].is("=") {
+                index = skip_expression(tokens, index + 1);
+            }
+        }
+    }
+    close + 1
+}
+
+// Maps the index of each binding name to the scope depth offset it lives at.
+// Parameters are bound inside of the body which follows them.
+fn find_bindings(tokens: &[Token]) -> HashMap<usize, i64> {
+    let mut bindings = HashMap::new();
+    let mut index = 0;
+
+    while index < tokens.len() {
+        let token = &tokens[index];
+        let mut targets = Vec::new();
+        let mut offset = 0;
+
+        if token.kind == Kind::Ident && (token.is("var") || token.is("let") || token.is("const")) {
+            let mut next = index + 1;
+            loop {
+                next = read_pattern(tokens, next, &mut targets);
+                // TypeScript type annotations.
+                if next < tokens.len() && tokens[next].is(":") {
+                    next = skip_expression(tokens, next + 1);
+                }
+                if next < tokens.len() && tokens[next].is("=") {
+                    next = skip_expression(tokens, next + 1);
+                }
+                if next < tokens.len() && tokens[next].is(",") {
+                    next += 1;
+                } else {
+                    break;
+                }
+            }
+        } else if token.is("function") {
+            let mut next = index + 1;
+            while next < tokens.len() && !tokens[next].is("(") { next += 1; }
+            if next < tokens.len() {
+                read_parameters(tokens, next, &mut targets);
+                offset = 1;
+            }
+        } else if token.is("=>") && index > 0 {
+            let previous = index - 1;
+            if tokens[previous].kind == Kind::Ident {
+                targets.push(previous);
+            } else if tokens[previous].is(")") {
+                let mut open = previous;
+                let mut depth = 0;
+                loop {
+                    if tokens[open].is(")") { depth += 1; }
+                    if tokens[open].is("(") { depth -= 1; }
+                    if depth == 0 || open == 0 { break; }
+                    open -= 1;
+                }
+                read_parameters(tokens, open, &mut targets);
+            }
+            if index + 1 < tokens.len() && tokens[index + 1].is("{") { offset = 1; }
+        } else if token.kind == Kind::Ident && index + 1 < tokens.len() && tokens[index + 1].is("(") {
+            // Methods, `name(a, b) { ... }`, and `catch (e) { ... }`.
+            let control = token.is("if") || token.is("for") || token.is("while") ||
+                token.is("switch") || token.is("function");
+            let close = matching(tokens, index + 1);
+            if !control && close + 1 < tokens.len() && tokens[close + 1].is("{") {
+                read_parameters(tokens, index + 1, &mut targets);
+                offset = 1;
+            }
+        }
+
+        for target in targets {
+            bindings.insert(target, offset);
+        }
+        index += 1;
+    }
+
+    bindings
+}
+
+fn format_key_javascript(original_string: String) -> String {
+    let tokens = tokenize_javascript(&original_string);
+    let bindings = find_bindings(&tokens);
+
+    let mut scope = scope::Scope::new();
+    let mut key = String::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 { key.push(' '); }
+        match token.kind {
+            Kind::Punct => {
+                if token.is("{") { scope.increase_depth(); }
+                if token.is("}") { scope.decrease_depth(); }
+                key.push_str(&token.text);
+            },
+            Kind::Literal => key.push_str(&token.text),
+            Kind::Ident => {
+                if let Some(offset) = bindings.get(&index) {
+                    for _ in 0..*offset { scope.increase_depth(); }
+                    scope.add_variable(token.text.clone());
+                    let replacement = scope.get_variable(token.text.clone()).unwrap();
+                    for _ in 0..*offset { scope.decrease_depth(); }
+                    key.push_str(&replacement);
+                    continue;
+                }
+                // Property names, `a.b` and `{ b: 1 }`, are never renamed.
+                let property = index > 0 && (tokens[index - 1].is(".") || tokens[index - 1].is("?.")) ||
+                    (index + 1 < tokens.len() && tokens[index + 1].is(":") &&
+                     index > 0 && (tokens[index - 1].is("{") || tokens[index - 1].is(",")));
+                match scope.get_variable(token.text.clone()) {
+                    Some(ref replacement) if !property => key.push_str(replacement),
+                    _ => key.push_str(&token.text),
+                }
+            },
+        }
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_key_javascript;
+
+    #[test]
+    fn renamed_bindings_match() {
+        let old = "const { a, b: c } = load();\nlet total = items.map((x) => x * a);\n";
+        let new = "const { a, b: d } = load();\nlet sum = items.map((y) => y * a);\n";
+        assert_eq!(format_key_javascript(String::from_str(old)), format_key_javascript(String::from_str(new)));
+    }
+
+    #[test]
+    fn comments_and_template_literals() {
+        let old = "// Greet.\nfunction greet(name) { return `hi ${name}`; }\n";
+        let new = "function greet(who) { /* Greet. */ return `hi ${who}`; }\n";
+        assert_eq!(format_key_javascript(String::from_str(old)), format_key_javascript(String::from_str(new)));
+    }
+
+    #[test]
+    fn regex_literals_are_not_division() {
+        let old = "var re = /a\\/b/g;\n";
+        let new = "var pattern = /a\\/b/g;\n";
+        assert_eq!(format_key_javascript(String::from_str(old)), format_key_javascript(String::from_str(new)));
+    }
+}
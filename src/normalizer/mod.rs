@@ -0,0 +1,184 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+pub mod rust;
+pub mod python;
+pub mod javascript;
+pub mod c;
+pub mod go;
+pub mod java;
+
+/// Turns a hunk of code into a key which can be compared against the keys of
+/// other hunks. Two hunks which produce the same key are considered a move.
+pub trait KeyNormalizer: Send + Sync {
+    /// A short name for the normalizer, eg. `"rust"`.
+    fn name(&self) -> &str;
+    /// Whether or not this normalizer understands the file at `path`.
+    fn handles(&self, path: &Path) -> bool;
+    /// Reduce the hunk into a key.
+    fn normalize(&self, hunk: String) -> String;
+    /// Like `normalize`, but also returns `(replacement, original)` pairs for
+    /// each name which was replaced in the key, so renames can be reported.
+    fn normalize_with_names(&self, hunk: String) -> (String, Vec<(String, String)>) {
+        (self.normalize(hunk), Vec::new())
+    }
+}
+
+/// Checks if the extension of `path` is any of `extensions`.
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => extensions.iter().any(|candidate| *candidate == ext),
+        None => false,
+    }
+}
+
+// What the tokenizer helpers below need from each language's tokens.
+trait Lexeme {
+    // Whether the token is the punctuation or keyword `text`, not a literal or
+    // name which happens to be spelled the same.
+    fn is(&self, text: &str) -> bool;
+}
+
+// Finds the index of the bracket which closes the one opened at `open`, or
+// `tokens.len()` if the hunk ends first.
+fn matching<T: Lexeme>(tokens: &[T], open: usize) -> usize {
+    let (opening, closing) = if tokens[open].is("(") {
+        ("(", ")")
+    } else if tokens[open].is("[") {
+        ("[", "]")
+    } else {
+        ("{", "}")
+    };
+    let mut depth = 0;
+    for index in open..tokens.len() {
+        if tokens[index].is(opening) {
+            depth += 1;
+        } else if tokens[index].is(closing) {
+            depth -= 1;
+            if depth == 0 { return index; }
+        }
+    }
+    tokens.len()
+}
+
+// Finds parentheses which are followed by a body, eg. parameters or a `for`
+// header. Names declared inside of them belong to the body's scope. `skip`
+// steps over what may come between the `)` and the body, and `bodies` are the
+// tokens a body can start with.
+fn find_headers<T: Lexeme, F>(tokens: &[T], bodies: &[&str], skip: F) -> HashSet<usize>
+    where F: Fn(&[T], usize) -> usize {
+    let mut headers = HashSet::new();
+    for index in 0..tokens.len() {
+        if !tokens[index].is("(") { continue; }
+        let close = matching(tokens, index);
+        let next = skip(tokens, close + 1);
+        if next < tokens.len() && bodies.iter().any(|body| tokens[next].is(body)) {
+            headers.insert(index);
+            headers.insert(close);
+        }
+    }
+    headers
+}
+
+// Returns the index just past the end of the quoted literal starting at
+// `start`. Backslashes escape, and a literal which reaches the end of the line
+// is unterminated, as the hunk probably started inside of it.
+fn scan_string(chars: &[char], start: usize) -> usize {
+    scan_until(chars, start + 1, &[chars[start]], true, false)
+}
+
+// Returns the index just past `terminator`, looking from `start`, for
+// literals like raw strings and text blocks which end differently.
+fn scan_until(chars: &[char], start: usize, terminator: &[char], escapes: bool, multiline: bool) -> usize {
+    let mut i = start;
+    while i < chars.len() {
+        if escapes && chars[i] == '\\' {
+            i += 2;
+        } else if chars[i..].starts_with(terminator) {
+            return i + terminator.len();
+        } else if !multiline && chars[i] == '\n' {
+            return i;
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+/// Strips whitespace. Used for any file which no other normalizer handles.
+pub struct Plain;
+
+impl KeyNormalizer for Plain {
+    fn name(&self) -> &str { "plain" }
+    fn handles(&self, _: &Path) -> bool { true }
+    fn normalize(&self, hunk: String) -> String { format_key(hunk) }
+}
+
+pub fn format_key(key: String) -> String {
+    let remove_whitespace = regex!(r"\s{2,}"); // 2 or more whitespaces
+    let trim = regex!(r"^[\s]+|[\s]+$");
+    let result = remove_whitespace.replace_all(&key[..], "");
+    trim.replace_all(&result[..], "")
+}
+
+/// Picks which `KeyNormalizer` to use for a given file. Clones share the
+/// normalizers, so they can be handed to other threads.
+#[derive(Clone)]
+pub struct Registry {
+    normalizers: Vec<Arc<Box<KeyNormalizer>>>,
+    fallback: Arc<Box<KeyNormalizer>>,
+}
+
+impl Registry {
+    /// A registry with no language support, every file is handled by `Plain`.
+    pub fn new() -> Registry {
+        Registry {
+            normalizers: Vec::new(),
+            fallback: Arc::new(Box::new(Plain)),
+        }
+    }
+
+    /// Add a normalizer. Normalizers registered later take priority, so
+    /// built in normalizers can be overridden.
+    pub fn register(&mut self, normalizer: Box<KeyNormalizer>) {
+        self.normalizers.push(Arc::new(normalizer));
+    }
+
+    /// The names of the normalizers, in the order they were registered.
+    pub fn names(&self) -> Vec<&str> {
+        self.normalizers.iter().map(|normalizer| normalizer.name()).collect()
+    }
+
+    pub fn fallback(&self) -> &KeyNormalizer {
+        &**self.fallback
+    }
+
+    /// Find the normalizer for a delta between `old_path` and `new_path`.
+    pub fn find(&self, old_path: &Path, new_path: &Path) -> &KeyNormalizer {
+        // Different languages can't really be compared.
+        if old_path.extension() != new_path.extension() {
+            return self.fallback();
+        }
+        for normalizer in self.normalizers.iter().rev() {
+            if normalizer.handles(old_path) {
+                return &***normalizer;
+            }
+        }
+        self.fallback()
+    }
+}
+
+impl Default for Registry {
+    /// A registry with all of the built in normalizers.
+    fn default() -> Registry {
+        let mut registry = Registry::new();
+        registry.register(Box::new(rust::Rust::new()));
+        registry.register(Box::new(python::Python));
+        registry.register(Box::new(javascript::JavaScript));
+        registry.register(Box::new(c::C));
+        registry.register(Box::new(go::Go));
+        registry.register(Box::new(java::Java));
+        registry
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use normalizer::{KeyNormalizer, has_extension, scan_string, scan_until};
+use scope;
+
+/// Keeps relative indentation, since it is syntax, and renames bindings.
+pub struct Python;
+
+impl KeyNormalizer for Python {
+    fn name(&self) -> &str { "python" }
+    fn handles(&self, path: &Path) -> bool { has_extension(path, &["py", "pyw"]) }
+    fn normalize(&self, hunk: String) -> String { format_key_python(hunk) }
+}
+
+const KEYWORDS: &'static [&'static str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break",
+    "class", "continue", "def", "del", "elif", "else", "except", "finally",
+    "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal",
+    "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+];
+
+const OPERATORS: &'static [&'static str] = &[
+    "**=", "//=", ">>=", "<<=", "...",
+    "==", "!=", "<=", ">=", "->", "**", "//", "<<", ">>", ":=",
+    "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "@=",
+];
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|keyword| *keyword == word)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Token {
+    Ident(String),
+    Literal(String),
+    Op(String),
+}
+
+impl Token {
+    fn is_op(&self, op: &str) -> bool {
+        match *self {
+            Token::Op(ref found) => found == op,
+            _ => false,
+        }
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        match *self {
+            Token::Ident(ref found) => found == ident,
+            _ => false,
+        }
+    }
+}
+
+// A logical line. Brackets and `\` can join many physical lines into one.
+#[derive(Debug)]
+struct Line {
+    indent: usize,
+    tokens: Vec<Token>,
+}
+
+// Returns the index just past the end of the string literal starting at
+// `start`. Triple quoted strings may span lines.
+fn scan_literal(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let triple = [quote, quote, quote];
+    if chars[start..].starts_with(&triple) {
+        scan_until(chars, start + 3, &triple, true, true)
+    } else {
+        scan_string(chars, start)
+    }
+}
+
+fn tokenize_python(hunk: &str) -> Vec<Line> {
+    let chars: Vec<char> = hunk.chars().collect();
+    let mut lines = Vec::new();
+    let mut tokens = Vec::new();
+    let mut indent = 0;
+    let mut at_line_start = true;
+    let mut depth = 0;
+    let mut i = 0;
+
+    while i < chars.len() {
+        if at_line_start {
+            // Tabs advance to the next multiple of 8, like CPython.
+            let mut width = 0;
+            while i < chars.len() && (chars[i] == ' ' || chars[i] == '\t') {
+                width = if chars[i] == '\t' { (width / 8 + 1) * 8 } else { width + 1 };
+                i += 1;
+            }
+            indent = width;
+            at_line_start = false;
+            continue;
+        }
+
+        let c = chars[i];
+        if c == '\n' {
+            i += 1;
+            // Newlines inside of brackets don't end the logical line.
+            if depth > 0 { continue; }
+            if !tokens.is_empty() {
+                lines.push(Line { indent: indent, tokens: tokens });
+                tokens = Vec::new();
+            }
+            at_line_start = true;
+        } else if c == '\\' && i + 1 < chars.len() && chars[i + 1] == '\n' {
+            i += 2;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '#' {
+            while i < chars.len() && chars[i] != '\n' { i += 1; }
+        } else if c == '"' || c == '\'' {
+            let end = scan_literal(&chars, i);
+            tokens.push(Token::Literal(chars[i..end].iter().cloned().collect()));
+            i = end;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') { i += 1; }
+            let word: String = chars[start..i].iter().cloned().collect();
+            // String prefixes, eg. `r"..."`, `b'...'` and `f"..."`.
+            let is_prefix = word.len() <= 2 && word.chars().all(|p| "rRbBuUfF".contains(p));
+            if is_prefix && i < chars.len() && (chars[i] == '"' || chars[i] == '\'') {
+                let end = scan_literal(&chars, i);
+                tokens.push(Token::Literal(chars[start..end].iter().cloned().collect()));
+                i = end;
+            } else {
+                tokens.push(Token::Ident(word));
+            }
+        } else if c.is_digit(10) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') { i += 1; }
+            tokens.push(Token::Literal(chars[start..i].iter().cloned().collect()));
+        } else {
+            let rest: String = chars[i..].iter().take(3).cloned().collect();
+            let op = match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                Some(op) => String::from_str(op),
+                None => c.to_string(),
+            };
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                // A hunk can start inside of brackets it never opened.
+                ')' | ']' | '}' => if depth > 0 { depth -= 1 },
+                _ => (),
+            }
+            i += op.chars().count();
+            tokens.push(Token::Op(op));
+        }
+    }
+
+    if !tokens.is_empty() {
+        lines.push(Line { indent: indent, tokens: tokens });
+    }
+
+    lines
+}
+
+// Finds the names introduced by a logical line. The names are bound in the
+// current scope, the indices are of `def` parameters, bound in the body below it.
+fn find_bindings(tokens: &[Token]) -> (Vec<String>, Vec<usize>) {
+    let mut locals = Vec::new();
+    let mut params = Vec::new();
+
+    let is_def = tokens.iter().take(2).any(|t| t.is_ident("def"));
+    if is_def {
+        let mut depth = 0;
+        for (index, token) in tokens.iter().enumerate() {
+            match *token {
+                Token::Op(ref op) if op == "(" || op == "[" => depth += 1,
+                Token::Op(ref op) if op == ")" || op == "]" => depth -= 1,
+                Token::Ident(ref name) if depth == 1 && !is_keyword(name) => {
+                    let after_separator = index > 0 && match tokens[index - 1] {
+                        Token::Op(ref op) => op == "(" || op == "," || op == "*" || op == "**",
+                        _ => false,
+                    };
+                    if after_separator { params.push(index); }
+                },
+                _ => (),
+            }
+        }
+        return (locals, params);
+    }
+
+    // `for x in`, `with y as z`, `lambda a, b:` and `(n := 1)`. Comprehensions use `for` too.
+    let mut index = 0;
+    while index < tokens.len() {
+        match tokens[index] {
+            Token::Ident(ref word) if word == "for" || word == "lambda" => {
+                let end = if word == "for" { "in" } else { ":" };
+                index += 1;
+                while index < tokens.len() && !tokens[index].is_ident(end) && !tokens[index].is_op(end) {
+                    if let Token::Ident(ref name) = tokens[index] {
+                        if !is_keyword(name) { locals.push(name.clone()); }
+                    }
+                    index += 1;
+                }
+            },
+            Token::Ident(ref word) if word == "as" => {
+                if let Some(&Token::Ident(ref name)) = tokens.get(index + 1) {
+                    locals.push(name.clone());
+                }
+            },
+            Token::Op(ref op) if op == ":=" && index > 0 => {
+                if let Token::Ident(ref name) = tokens[index - 1] {
+                    locals.push(name.clone());
+                }
+            },
+            _ => (),
+        }
+        index += 1;
+    }
+
+    // Assignment targets are everything before the last top level `=`.
+    let mut depth = 0;
+    let mut last_assign = None;
+    let mut annotation = None;
+    for (index, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Op(ref op) if op == "(" || op == "[" || op == "{" => depth += 1,
+            Token::Op(ref op) if op == ")" || op == "]" || op == "}" => depth -= 1,
+            Token::Op(ref op) if op == "=" && depth == 0 => last_assign = Some(index),
+            Token::Op(ref op) if op == ":" && depth == 0 && annotation.is_none() => annotation = Some(index),
+            _ => (),
+        }
+    }
+    if let Some(assign) = last_assign {
+        // `x: int = 1` only binds `x`.
+        let end = match annotation {
+            Some(colon) if colon < assign => colon,
+            _ => assign,
+        };
+        // Whether each open bracket is a subscript or call, like `d[k]`, whose
+        // names are read rather than bound, or a tuple or list of targets.
+        let mut brackets: Vec<bool> = Vec::new();
+        let mut index = 0;
+        while index < end {
+            match tokens[index] {
+                Token::Op(ref op) if op == "(" || op == "[" || op == "{" => {
+                    let subscript = index > 0 && match tokens[index - 1] {
+                        Token::Ident(ref name) => !is_keyword(name),
+                        Token::Literal(_) => true,
+                        Token::Op(ref op) => op == ")" || op == "]" || op == "}",
+                    };
+                    brackets.push(subscript);
+                },
+                Token::Op(ref op) if op == ")" || op == "]" || op == "}" => { brackets.pop(); },
+                Token::Ident(_) if brackets.iter().any(|subscript| *subscript) => (),
+                Token::Ident(ref name) if !is_keyword(name) => {
+                    let attribute = index > 0 && tokens[index - 1].is_op(".");
+                    let followed = match tokens.get(index + 1) {
+                        Some(next) => next.is_op(".") || next.is_op("(") || next.is_op("["),
+                        None => false,
+                    };
+                    if !attribute && !followed { locals.push(name.clone()); }
+                },
+                _ => (),
+            }
+            index += 1;
+        }
+    }
+
+    (locals, params)
+}
+
+fn format_key_python(original_string: String) -> String {
+    let lines = tokenize_python(&original_string);
+
+    // Indentation is compared by level, not width, so a block which was moved
+    // and re-indented keeps the same key.
+    let mut widths: Vec<usize> = lines.iter().map(|line| line.indent).collect();
+    widths.sort();
+    widths.dedup();
+
+    let mut scope = scope::Scope::new();
+    let mut key = String::new();
+    let mut level = 0;
+
+    for line in lines {
+        let line_level = widths.iter().position(|width| *width == line.indent).unwrap_or(0);
+        while level < line_level {
+            scope.increase_depth();
+            level += 1;
+        }
+        while level > line_level {
+            scope.decrease_depth();
+            level -= 1;
+        }
+
+        let (locals, params) = find_bindings(&line.tokens);
+        for name in locals {
+            if scope.get_variable(name.clone()).is_none() {
+                scope.add_variable(name);
+            }
+        }
+        // Parameters are renamed on the `def` line too, as the body sees them.
+        scope.increase_depth();
+        let mut renamed = HashMap::new();
+        for index in params {
+            if let Token::Ident(ref name) = line.tokens[index] {
+                scope.add_variable(name.clone());
+                renamed.insert(index, scope.get_variable(name.clone()).unwrap());
+            }
+        }
+        scope.decrease_depth();
+
+        for _ in 0..line_level {
+            key.push('\t');
+        }
+        let mut depth = 0;
+        for (index, token) in line.tokens.iter().enumerate() {
+            if index > 0 { key.push(' '); }
+            match *token {
+                Token::Ident(_) if renamed.contains_key(&index) => key.push_str(&renamed[&index]),
+                Token::Ident(ref name) => {
+                    let attribute = index > 0 && line.tokens[index - 1].is_op(".");
+                    let keyword_arg = depth > 0 && match line.tokens.get(index + 1) {
+                        Some(next) => next.is_op("="),
+                        None => false,
+                    };
+                    match scope.get_variable(name.clone()) {
+                        Some(ref replacement) if !attribute && !keyword_arg => key.push_str(replacement),
+                        _ => key.push_str(name),
+                    }
+                },
+                Token::Literal(ref literal) => key.push_str(literal),
+                Token::Op(ref op) => {
+                    match &op[..] {
+                        "(" | "[" | "{" => depth += 1,
+                        ")" | "]" | "}" => depth -= 1,
+                        _ => (),
+                    }
+                    key.push_str(op);
+                },
+            }
+        }
+        key.push('\n');
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_key_python;
+
+    #[test]
+    fn reindented_move_matches() {
+        let old = "def area(w, h):\n    result = w * h\n    return result\n";
+        let new = "    def area(width, height):\n        total = width * height\n        return total\n";
+        assert_eq!(format_key_python(String::from_str(old)), format_key_python(String::from_str(new)));
+    }
+
+    #[test]
+    fn comments_are_ignored() {
+        let old = "x = 1  # one\nprint(x)\n";
+        let new = "# A note.\ny = 1\nprint(y)\n";
+        assert_eq!(format_key_python(String::from_str(old)), format_key_python(String::from_str(new)));
+    }
+
+    #[test]
+    fn relative_indentation_matters() {
+        let old = "if a:\n    b()\nc()\n";
+        let new = "if a:\n    b()\n    c()\n";
+        assert!(format_key_python(String::from_str(old)) != format_key_python(String::from_str(new)));
+    }
+
+    #[test]
+    fn subscripts_are_not_bindings() {
+        // `key` is read, so it keeps its name.
+        let old = "d[key] = value\n";
+        let new = "d[other] = value\n";
+        assert!(format_key_python(String::from_str(old)) != format_key_python(String::from_str(new)));
+        // Names in a tuple of targets are still bound.
+        let old = "(a, b) = pair\nprint(a)\n";
+        let new = "(x, y) = pair\nprint(x)\n";
+        assert_eq!(format_key_python(String::from_str(old)), format_key_python(String::from_str(new)));
+    }
+
+    #[test]
+    fn renamed_parameters_match() {
+        let old = "def f(a, b=1):\n    return a + b\n";
+        let new = "def f(x, y=1):\n    return x + y\n";
+        assert_eq!(format_key_python(String::from_str(old)), format_key_python(String::from_str(new)));
+    }
+}
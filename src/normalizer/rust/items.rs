@@ -0,0 +1,236 @@
+// Splits Rust source into items. This isn't a full parser, it only finds where
+// items start and end, which is enough to compare them.
+
+use std::cmp;
+
+use normalizer::matching;
+use normalizer::rust::lexer::{self, Kind, Token};
+
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// `fn`, `struct`, `enum`, `impl`, `trait`, `mod` or `macro_rules`.
+    pub kind: String,
+    /// The name, or for an `impl` the type (and trait) it is for.
+    pub name: String,
+    /// Names of the enclosing `mod`, `impl` and `trait` items.
+    pub parents: Vec<String>,
+    /// The index of the enclosing item, if any.
+    pub parent: Option<usize>,
+    /// The first line, starting at 1.
+    pub line: u32,
+    pub line_count: u32,
+    /// The source of the whole item, attributes and doc comments excluded.
+    pub source: String,
+}
+
+impl Item {
+    /// The path of the item inside of its file, eg. `Parser::parse`.
+    pub fn path(&self) -> String {
+        let mut segments = self.parents.clone();
+        segments.push(self.name.clone());
+        segments.connect("::")
+    }
+}
+
+// Words which can come before the item keyword, eg. `pub(crate) unsafe fn`.
+const MODIFIERS: &'static [&'static str] = &[
+    "pub", "unsafe", "extern", "const", "async", "default",
+];
+
+// Finds the `{` or `;` which ends an item's header, skipping over brackets.
+fn find_body(tokens: &[Token], start: usize) -> usize {
+    let mut index = start;
+    while index < tokens.len() {
+        if tokens[index].is("{") || tokens[index].is(";") { return index; }
+        if tokens[index].is("(") || tokens[index].is("[") {
+            index = matching(tokens, index);
+        }
+        index += 1;
+    }
+    tokens.len() - 1
+}
+
+fn line_of(source: &str, offset: usize) -> u32 {
+    source[..offset].chars().filter(|c| *c == '\n').count() as u32 + 1
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token<'a>>,
+    items: Vec<Item>,
+}
+
+impl<'a> Parser<'a> {
+    // Parses items between `start` and `end`, which are inside of `parent`.
+    fn parse(&mut self, start: usize, end: usize, parents: &Vec<String>, parent: Option<usize>) {
+        let mut index = start;
+        while index < end {
+            // Skip attributes, `#[...]` and `#![...]`.
+            if self.tokens[index].is("#") {
+                let mut open = index + 1;
+                if open < end && self.tokens[open].is("!") { open += 1; }
+                if open < end && self.tokens[open].is("[") {
+                    index = matching(&self.tokens, open) + 1;
+                    continue;
+                }
+            }
+            let first = index;
+            while index < end && MODIFIERS.iter().any(|m| self.tokens[index].is(m)) {
+                index += 1;
+                // `pub(crate)` and `extern "C"`.
+                if index < end && self.tokens[index].is("(") {
+                    index = matching(&self.tokens, index) + 1;
+                } else if index < end && self.tokens[index].kind == Kind::Literal {
+                    index += 1;
+                }
+            }
+            if index >= end { break; }
+            let keyword = self.tokens[index].text;
+            let last = match keyword {
+                "fn" | "struct" | "enum" | "union" | "trait" | "mod" | "impl" => {
+                    self.parse_item(first, index, end, parents, parent)
+                },
+                "macro_rules" if index + 2 < end && self.tokens[index + 1].is("!") => {
+                    let name = String::from_str(self.tokens[index + 2].text);
+                    let body = find_body(&self.tokens, index + 3);
+                    let last = if self.tokens[body].is("{") {
+                        cmp::min(matching(&self.tokens, body), self.tokens.len() - 1)
+                    } else {
+                        body
+                    };
+                    self.push(first, last, "macro_rules", name, parents, parent);
+                    last
+                },
+                _ => {
+                    // Not an item we track, skip the whole statement or block.
+                    let body = find_body(&self.tokens, index);
+                    if self.tokens[body].is("{") { matching(&self.tokens, body) } else { body }
+                },
+            };
+            index = last + 1;
+        }
+    }
+
+    // Parses an item whose keyword is at `keyword`, returning the index of its last token.
+    fn parse_item(&mut self, first: usize, keyword: usize, end: usize, parents: &Vec<String>,
+                  parent: Option<usize>) -> usize {
+        let kind = self.tokens[keyword].text;
+        let body = find_body(&self.tokens, keyword + 1);
+        let name = if kind == "impl" {
+            // `impl<T> Trait for Type<T> where ...`, named after everything up to `where`.
+            let mut words = String::new();
+            let mut index = keyword + 1;
+            if index < body && self.tokens[index].is("<") {
+                let mut depth = 0;
+                while index < body {
+                    if self.tokens[index].is("<") { depth += 1; }
+                    if self.tokens[index].is(">") { depth -= 1; }
+                    // The lexer reads `>>` as one token, eg. in `impl<T: Iterator<Item=u8>>`.
+                    if self.tokens[index].is(">>") { depth -= 2; }
+                    index += 1;
+                    if depth <= 0 { break; }
+                }
+            }
+            let mut previous_word = false;
+            while index < body && !self.tokens[index].is("where") {
+                let token = &self.tokens[index];
+                let word = token.kind == Kind::Ident || token.kind == Kind::Keyword;
+                if word && previous_word { words.push(' '); }
+                words.push_str(token.text);
+                previous_word = word;
+                index += 1;
+            }
+            words
+        } else {
+            match self.tokens.get(keyword + 1) {
+                Some(token) if token.kind == Kind::Ident => String::from_str(token.text),
+                _ => return body,
+            }
+        };
+        // An unclosed body runs to the end of the file.
+        let last = if self.tokens[body].is("{") {
+            cmp::min(matching(&self.tokens, body), self.tokens.len() - 1)
+        } else {
+            body
+        };
+        // `mod name;` has its items in another file.
+        if kind == "mod" && self.tokens[body].is(";") { return last; }
+
+        let this = self.push(first, last, kind, name.clone(), parents, parent);
+        if (kind == "mod" || kind == "impl" || kind == "trait") && self.tokens[body].is("{") {
+            let mut inner = parents.clone();
+            inner.push(name);
+            self.parse(body + 1, cmp::min(last, end), &inner, Some(this));
+        }
+        last
+    }
+
+    fn push(&mut self, first: usize, last: usize, kind: &str, name: String, parents: &Vec<String>,
+            parent: Option<usize>) -> usize {
+        let (start, end) = (self.tokens[first].span.start, self.tokens[last].span.end);
+        let line = line_of(self.source, start);
+        self.items.push(Item {
+            kind: String::from_str(kind),
+            name: name,
+            parents: parents.clone(),
+            parent: parent,
+            line: line,
+            line_count: line_of(self.source, end) - line + 1,
+            source: String::from_str(&self.source[start..end]),
+        });
+        self.items.len() - 1
+    }
+}
+
+/// Finds the `fn`, `struct`, `enum`, `impl`, `trait`, `mod` and `macro_rules`
+/// items in a Rust file, including those nested in other items.
+pub fn parse_items(source: &str) -> Vec<Item> {
+    let tokens: Vec<Token> = lexer::tokenize(source).into_iter()
+        .filter(|token| token.kind != Kind::Comment)
+        .collect();
+    if tokens.is_empty() { return Vec::new(); }
+    let end = tokens.len();
+    let mut parser = Parser {
+        source: source,
+        tokens: tokens,
+        items: Vec::new(),
+    };
+    parser.parse(0, end, &Vec::new(), None);
+    parser.items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_items;
+
+    #[test]
+    fn nested_items() {
+        let source = "mod a {\n    pub struct S;\n    impl S {\n        fn f(&self) {}\n    }\n}\nmacro_rules! m { () => {} }\n";
+        let paths: Vec<(String, String)> = parse_items(source).iter()
+            .map(|item| (item.kind.clone(), item.path()))
+            .collect();
+        assert_eq!(paths, vec![
+            (String::from_str("mod"), String::from_str("a")),
+            (String::from_str("struct"), String::from_str("a::S")),
+            (String::from_str("impl"), String::from_str("a::S")),
+            (String::from_str("fn"), String::from_str("a::S::f")),
+            (String::from_str("macro_rules"), String::from_str("m")),
+        ]);
+    }
+
+    #[test]
+    fn lines() {
+        let items = parse_items("use x;\n\n#[test]\nfn f() {\n}\n");
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].line, 4);
+        assert_eq!(items[0].line_count, 2);
+    }
+
+    #[test]
+    fn nested_impl_generics() {
+        let items = parse_items("impl<T: Iterator<Item=u8>> Foo for T {\n    fn f(&self) {}\n}\n");
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].name, "Foo for T");
+        assert_eq!(items[1].path(), "Foo for T::f");
+    }
+}
@@ -0,0 +1,308 @@
+// A lexer for Rust source. It is forgiving, since hunks rarely start or end at
+// token boundaries, so anything it doesn't understand becomes punctuation.
+
+use normalizer::Lexeme;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Kind {
+    Ident, Keyword, Literal, Punct, Lifetime, Comment
+}
+
+/// Byte offsets into the source, `start` inclusive and `end` exclusive.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Token<'a> {
+    pub kind: Kind,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+impl<'a> Token<'a> {
+    pub fn is(&self, text: &str) -> bool {
+        match self.kind {
+            Kind::Punct | Kind::Keyword => self.text == text,
+            _ => false,
+        }
+    }
+}
+
+impl<'a> Lexeme for Token<'a> {
+    fn is(&self, text: &str) -> bool { Token::is(self, text) }
+}
+
+const KEYWORDS: &'static [&'static str] = &[
+    "as", "box", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match",
+    "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static",
+    "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+];
+
+const PUNCTUATION: &'static [&'static str] = &[
+    "<<=", ">>=", "...", "..=", "::", "->", "=>", "==", "!=", "<=", ">=", "&&",
+    "||", "+=", "-=", "*=", "/=", "%=", "^=", "&=", "|=", "<<", ">>", "..",
+];
+
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|keyword| *keyword == word)
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Vec<(usize, char)>,
+    position: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.position + ahead).map(|&(_, c)| c)
+    }
+
+    fn offset(&self) -> usize {
+        match self.chars.get(self.position) {
+            Some(&(offset, _)) => offset,
+            None => self.source.len(),
+        }
+    }
+
+    fn eat_while<F>(&mut self, predicate: F) where F: Fn(char) -> bool {
+        while let Some(c) = self.peek(0) {
+            if !predicate(c) { break; }
+            self.position += 1;
+        }
+    }
+
+    // `"..."`, starting at the opening quote.
+    fn eat_string(&mut self) {
+        self.position += 1;
+        while let Some(c) = self.peek(0) {
+            self.position += 1;
+            if c == '\\' {
+                self.position += 1;
+            } else if c == '"' {
+                return;
+            }
+        }
+    }
+
+    // `r#"..."#`, starting at the `r`.
+    fn eat_raw_string(&mut self) {
+        self.position += 1;
+        let mut hashes = 0;
+        while self.peek(0) == Some('#') {
+            hashes += 1;
+            self.position += 1;
+        }
+        self.position += 1; // The opening quote.
+        while let Some(c) = self.peek(0) {
+            self.position += 1;
+            if c == '"' && (0..hashes).all(|ahead| self.peek(ahead) == Some('#')) {
+                self.position += hashes;
+                return;
+            }
+        }
+    }
+
+    // `'a'`, `'\n'` or `'a`, starting at the quote.
+    fn eat_quote(&mut self) -> Kind {
+        let is_char = match (self.peek(1), self.peek(2)) {
+            (Some('\\'), _) => true,
+            (Some(_), Some('\'')) => true,
+            (Some(c), _) => !is_ident_start(c),
+            (None, _) => false,
+        };
+        self.position += 1;
+        if is_char {
+            while let Some(c) = self.peek(0) {
+                self.position += 1;
+                if c == '\\' {
+                    self.position += 1;
+                } else if c == '\'' || c == '\n' {
+                    break;
+                }
+            }
+            Kind::Literal
+        } else {
+            self.eat_while(is_ident_continue);
+            Kind::Lifetime
+        }
+    }
+
+    fn eat_number(&mut self) {
+        let start = self.position;
+        let hex = self.peek(0) == Some('0') && (self.peek(1) == Some('x') || self.peek(1) == Some('X'));
+        while let Some(c) = self.peek(0) {
+            let previous = if self.position > start { Some(self.chars[self.position - 1].1) } else { None };
+            // `1e-5`, but not `0xe - 5`.
+            let exponent = (c == '+' || c == '-') && !hex && (previous == Some('e') || previous == Some('E'));
+            // `1.5`, but not `1..2` or `1.max(2)`.
+            let decimal = c == '.' && self.peek(1).map_or(false, |next| next.is_digit(10));
+            if is_ident_continue(c) || decimal || exponent {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn next_kind(&mut self) -> Option<Kind> {
+        let c = match self.peek(0) {
+            Some(c) => c,
+            None => return None,
+        };
+        let next = self.peek(1);
+
+        let kind = if c == '/' && next == Some('/') {
+            self.eat_while(|c| c != '\n');
+            Kind::Comment
+        } else if c == '/' && next == Some('*') {
+            // Block comments nest.
+            let mut depth = 0;
+            while let Some(c) = self.peek(0) {
+                if c == '/' && self.peek(1) == Some('*') {
+                    depth += 1;
+                    self.position += 2;
+                } else if c == '*' && self.peek(1) == Some('/') {
+                    depth -= 1;
+                    self.position += 2;
+                    if depth == 0 { break; }
+                } else {
+                    self.position += 1;
+                }
+            }
+            Kind::Comment
+        } else if c == '"' {
+            self.eat_string();
+            Kind::Literal
+        } else if c == '\'' {
+            self.eat_quote()
+        } else if c == 'b' && next == Some('"') {
+            self.position += 1;
+            self.eat_string();
+            Kind::Literal
+        } else if c == 'b' && next == Some('\'') {
+            self.position += 1;
+            self.eat_quote();
+            Kind::Literal
+        } else if c == 'r' && (next == Some('"') || (next == Some('#') && !self.peek(2).map_or(false, is_ident_start))) {
+            self.eat_raw_string();
+            Kind::Literal
+        } else if c == 'b' && next == Some('r') && (self.peek(2) == Some('"') || self.peek(2) == Some('#')) {
+            self.position += 1;
+            self.eat_raw_string();
+            Kind::Literal
+        } else if c == 'r' && next == Some('#') {
+            // Raw identifiers, `r#match`.
+            self.position += 2;
+            self.eat_while(is_ident_continue);
+            Kind::Ident
+        } else if c == '_' && !self.peek(1).map_or(false, is_ident_continue) {
+            self.position += 1;
+            Kind::Punct
+        } else if is_ident_start(c) {
+            let start = self.offset();
+            self.eat_while(is_ident_continue);
+            if is_keyword(&self.source[start..self.offset()]) { Kind::Keyword } else { Kind::Ident }
+        } else if c.is_digit(10) {
+            self.eat_number();
+            Kind::Literal
+        } else {
+            let rest = &self.source[self.offset()..];
+            self.position += match PUNCTUATION.iter().find(|punct| rest.starts_with(**punct)) {
+                Some(punct) => punct.len(),
+                None => 1,
+            };
+            Kind::Punct
+        };
+
+        Some(kind)
+    }
+}
+
+/// Splits Rust source into tokens. Whitespace is dropped, comments are kept.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut lexer = Lexer {
+        source: source,
+        chars: source.char_indices().collect(),
+        position: 0,
+    };
+    let mut tokens = Vec::new();
+
+    loop {
+        lexer.eat_while(char::is_whitespace);
+        let start = lexer.offset();
+        let kind = match lexer.next_kind() {
+            Some(kind) => kind,
+            None => break,
+        };
+        // Unterminated literals can run past the end of the hunk.
+        if lexer.position > lexer.chars.len() { lexer.position = lexer.chars.len(); }
+        let end = lexer.offset();
+        tokens.push(Token {
+            kind: kind,
+            text: &source[start..end],
+            span: Span { start: start, end: end },
+        });
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, Kind};
+
+    fn kinds(source: &str) -> Vec<(Kind, &str)> {
+        tokenize(source).iter().map(|token| (token.kind, token.text)).collect()
+    }
+
+    #[test]
+    fn paths_and_arrows() {
+        assert_eq!(kinds("a::b -> c"), vec![
+            (Kind::Ident, "a"), (Kind::Punct, "::"), (Kind::Ident, "b"),
+            (Kind::Punct, "->"), (Kind::Ident, "c"),
+        ]);
+    }
+
+    #[test]
+    fn lifetimes_and_chars() {
+        assert_eq!(kinds("&'a str, 'b', '\\''"), vec![
+            (Kind::Punct, "&"), (Kind::Lifetime, "'a"), (Kind::Ident, "str"),
+            (Kind::Punct, ","), (Kind::Literal, "'b'"), (Kind::Punct, ","),
+            (Kind::Literal, "'\\''"),
+        ]);
+    }
+
+    #[test]
+    fn raw_strings() {
+        assert_eq!(kinds("r#\"a \"quoted\" b\"# x"), vec![
+            (Kind::Literal, "r#\"a \"quoted\" b\"#"), (Kind::Ident, "x"),
+        ]);
+    }
+
+    #[test]
+    fn quotes_in_comments() {
+        assert_eq!(kinds("// don't \"\nlet"), vec![
+            (Kind::Comment, "// don't \""), (Kind::Keyword, "let"),
+        ]);
+    }
+
+    #[test]
+    fn spans() {
+        let tokens = tokenize("fn  main");
+        assert_eq!(tokens[1].span.start, 4);
+        assert_eq!(tokens[1].span.end, 8);
+    }
+}
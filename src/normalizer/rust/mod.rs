@@ -0,0 +1,387 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::path::Path;
+
+use normalizer::{KeyNormalizer, has_extension, matching};
+use scope;
+
+use self::lexer::{Kind, Token};
+
+pub mod lexer;
+pub mod items;
+
+/// Renames bindings, from `let`, `for`, `match` arms, closures and `fn`
+/// parameters, so that moved code with renamed variables still matches.
+pub struct Rust {
+    deep: bool,
+}
+
+impl Rust {
+    pub fn new() -> Rust {
+        Rust { deep: false }
+    }
+
+    /// Also renames generic parameters, lifetimes and private fields which are
+    /// declared in the hunk, for items which were moved and renamed at once.
+    pub fn deep() -> Rust {
+        Rust { deep: true }
+    }
+}
+
+impl KeyNormalizer for Rust {
+    fn name(&self) -> &str { if self.deep { "rust-deep" } else { "rust" } }
+    fn handles(&self, path: &Path) -> bool { has_extension(path, &["rs"]) }
+    fn normalize(&self, hunk: String) -> String { normalize_rust(&hunk, self.deep).0 }
+    fn normalize_with_names(&self, hunk: String) -> (String, Vec<(String, String)>) {
+        normalize_rust(&hunk, self.deep)
+    }
+}
+
+// Finds the first of `stops` at the same bracket depth as `start`, or the
+// bracket which closes the depth `start` is in.
+fn find_top_level(tokens: &[Token], start: usize, stops: &[&str]) -> usize {
+    let mut depth = 0;
+    for index in start..tokens.len() {
+        let token = &tokens[index];
+        if depth == 0 && stops.iter().any(|stop| token.is(stop)) {
+            return index;
+        }
+        if token.is("(") || token.is("[") || token.is("{") {
+            depth += 1;
+        } else if token.is(")") || token.is("]") || token.is("}") {
+            if depth == 0 { return index; }
+            depth -= 1;
+        }
+    }
+    tokens.len()
+}
+
+// Skips a type, stopping at the first `,` outside of any brackets or generics.
+fn skip_type(tokens: &[Token], start: usize, end: usize) -> usize {
+    let mut depth = 0;
+    for index in start..end {
+        let token = &tokens[index];
+        if depth == 0 && token.is(",") { return index; }
+        match token.text {
+            "(" | "[" | "{" | "<" => depth += 1,
+            ")" | "]" | "}" | ">" => depth -= 1,
+            "<<" if token.kind == Kind::Punct => depth += 2,
+            ">>" if token.kind == Kind::Punct => depth -= 2,
+            _ => (),
+        }
+    }
+    end
+}
+
+// Records the names bound by the pattern between `start` and `end`. Paths,
+// enum variants, constants and struct field names are not bindings. `offset`
+// is from the depth the pattern starts at, so names inside of a struct
+// pattern's braces are bound outside of them.
+fn read_pattern(tokens: &[Token], start: usize, end: usize, offset: i64, bindings: &mut HashMap<usize, i64>) {
+    let mut braces = 0;
+    for index in start..end {
+        let token = &tokens[index];
+        if token.is("{") { braces += 1; }
+        if token.is("}") { braces -= 1; }
+        if token.kind != Kind::Ident { continue; }
+        let lowercase = token.text.chars().next().map_or(false, |c| c.is_lowercase() || c == '_');
+        let path = index > 0 && (tokens[index - 1].is("::") || tokens[index - 1].is("."));
+        let followed = match tokens.get(index + 1) {
+            Some(next) => next.is("(") || next.is("{") || next.is("::") || next.is("!") ||
+                (braces > 0 && next.is(":")),
+            None => false,
+        };
+        if lowercase && !path && !followed {
+            bindings.insert(index, offset - braces);
+        }
+    }
+}
+
+// Records the names bound by `pattern: Type` parameters between `open` and `close`.
+fn read_parameters(tokens: &[Token], open: usize, close: usize, offset: i64, bindings: &mut HashMap<usize, i64>) {
+    let mut start = open + 1;
+    while start < close {
+        let colon = cmp::min(find_top_level(tokens, start, &[":", ","]), close);
+        read_pattern(tokens, start, colon, offset, bindings);
+        start = skip_type(tokens, colon, close) + 1;
+    }
+}
+
+// Whether the `|` at `index` opens a closure's parameters rather than being a
+// binary or pattern `|`.
+fn is_closure_start(tokens: &[Token], index: usize) -> bool {
+    if index == 0 { return true; }
+    let previous = &tokens[index - 1];
+    match previous.kind {
+        Kind::Punct => !(previous.is(")") || previous.is("]") || previous.is("}") || previous.is("_")),
+        Kind::Keyword => !(previous.is("self") || previous.is("Self") || previous.is("true") || previous.is("false")),
+        _ => false,
+    }
+}
+
+// Maps the index of each binding name to the scope depth offset it lives at.
+// Names bound by `for`, `if let`, `while let` and `fn` live inside of the body.
+fn find_bindings(tokens: &[Token]) -> HashMap<usize, i64> {
+    let mut bindings = HashMap::new();
+    let mut index = 0;
+
+    while index < tokens.len() {
+        let token = &tokens[index];
+
+        if token.is("let") {
+            let conditional = index > 0 && (tokens[index - 1].is("if") || tokens[index - 1].is("while"));
+            let end = find_top_level(tokens, index + 1, &[":", "=", ";", "else"]);
+            read_pattern(tokens, index + 1, end, if conditional { 1 } else { 0 }, &mut bindings);
+        } else if token.is("for") && !tokens.get(index + 1).map_or(false, |next| next.is("<")) {
+            // `for x in`, but not `impl Trait for Type`.
+            let end = find_top_level(tokens, index + 1, &["in", "{", ";"]);
+            if end < tokens.len() && tokens[end].is("in") {
+                read_pattern(tokens, index + 1, end, 1, &mut bindings);
+            }
+        } else if token.is("fn") {
+            // Skip the name and any generics, whose bounds may hold `Fn(T)`.
+            let mut open = index + 1;
+            let mut generics = 0;
+            while open < tokens.len() {
+                let next = &tokens[open];
+                if next.is("<") { generics += 1; }
+                if next.is(">") { generics -= 1; }
+                if generics == 0 && (next.is("(") || next.is("{") || next.is(";")) { break; }
+                open += 1;
+            }
+            if open < tokens.len() && tokens[open].is("(") {
+                let close = matching(tokens, open);
+                read_parameters(tokens, open, close, 1, &mut bindings);
+            }
+        } else if token.is("|") && is_closure_start(tokens, index) {
+            let close = find_top_level(tokens, index + 1, &["|"]);
+            read_parameters(tokens, index, close, 0, &mut bindings);
+            index = close + 1;
+            continue;
+        } else if token.is("match") {
+            let open = find_top_level(tokens, index + 1, &["{"]);
+            if open < tokens.len() && tokens[open].is("{") {
+                let close = matching(tokens, open);
+                let mut start = open + 1;
+                while start < close {
+                    let arrow = find_top_level(tokens, start, &["=>"]);
+                    if arrow >= close { break; }
+                    let guard = find_top_level(tokens, start, &["if"]);
+                    read_pattern(tokens, start, cmp::min(arrow, guard), 0, &mut bindings);
+                    // Skip over the arm's body.
+                    let body = arrow + 1;
+                    start = if body < close && tokens[body].is("{") {
+                        let end = matching(tokens, body) + 1;
+                        if end < close && tokens[end].is(",") { end + 1 } else { end }
+                    } else {
+                        find_top_level(tokens, body, &[","]) + 1
+                    };
+                }
+            }
+        }
+
+        index += 1;
+    }
+
+    bindings
+}
+
+// Names declared by items in the hunk, mapped to their replacements. Generic
+// parameters and lifetimes are renamed everywhere, fields only where a field can be.
+struct ItemNames {
+    generics: HashMap<String, String>,
+    fields: HashMap<String, String>,
+}
+
+// Reads a generic parameter list, `<'a, T: Clone, const N: usize>`, from the `<` at `open`.
+fn read_generics(tokens: &[Token], open: usize, names: &mut ItemNames) -> usize {
+    let mut depth = 0;
+    for index in open..tokens.len() {
+        let token = &tokens[index];
+        match token.text {
+            "<" if token.kind == Kind::Punct => depth += 1,
+            ">" if token.kind == Kind::Punct => depth -= 1,
+            ">>" if token.kind == Kind::Punct => depth -= 2,
+            _ => (),
+        }
+        if depth <= 0 { return index; }
+        let declares = depth == 1 && index > 0 && (tokens[index - 1].is("<") || tokens[index - 1].is(",") ||
+                                                   tokens[index - 1].is("const"));
+        if !declares || names.generics.contains_key(token.text) { continue; }
+        let replacement = match token.kind {
+            Kind::Lifetime => format!("'l{}", names.generics.len()),
+            Kind::Ident => format!("g{}", names.generics.len()),
+            _ => continue,
+        };
+        names.generics.insert(String::from_str(token.text), replacement);
+    }
+    tokens.len()
+}
+
+fn find_item_names(tokens: &[Token]) -> ItemNames {
+    let mut names = ItemNames {
+        generics: HashMap::new(),
+        fields: HashMap::new(),
+    };
+
+    for index in 0..tokens.len() {
+        let token = &tokens[index];
+        if token.is("impl") && index + 1 < tokens.len() && tokens[index + 1].is("<") {
+            read_generics(tokens, index + 1, &mut names);
+            continue;
+        }
+        let item = token.is("fn") || token.is("struct") || token.is("enum") || token.is("trait") ||
+            token.is("type");
+        if !item || index + 1 >= tokens.len() || tokens[index + 1].kind != Kind::Ident { continue; }
+        let mut next = index + 2;
+        if next < tokens.len() && tokens[next].is("<") {
+            next = read_generics(tokens, next, &mut names) + 1;
+        }
+        if !token.is("struct") { continue; }
+        // Private fields, those without `pub`, of a struct declared in the hunk.
+        let open = find_top_level(tokens, next, &["{", ";"]);
+        if open >= tokens.len() || !tokens[open].is("{") { continue; }
+        let close = matching(tokens, open);
+        for field in open + 1..close {
+            let declared = tokens[field].kind == Kind::Ident &&
+                tokens.get(field + 1).map_or(false, |next| next.is(":")) &&
+                (tokens[field - 1].is("{") || tokens[field - 1].is(",") || tokens[field - 1].is("]"));
+            if declared && !names.fields.contains_key(tokens[field].text) {
+                let replacement = format!("f{}", names.fields.len());
+                names.fields.insert(String::from_str(tokens[field].text), replacement);
+            }
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+fn format_key_rust(original_string: String) -> String {
+    normalize_rust(&original_string, false).0
+}
+
+// Returns the key, and which original name each replacement in it stands for.
+fn normalize_rust(original_string: &str, deep: bool) -> (String, Vec<(String, String)>) {
+    // Comments never make it into the key, so their contents can't be renamed.
+    let tokens: Vec<Token> = lexer::tokenize(original_string).into_iter()
+        .filter(|t| t.kind != Kind::Comment)
+        .collect();
+    let bindings = find_bindings(&tokens);
+    let items = if deep {
+        find_item_names(&tokens)
+    } else {
+        ItemNames { generics: HashMap::new(), fields: HashMap::new() }
+    };
+
+    let mut scope = scope::Scope::new();
+    let mut key: Vec<String> = Vec::new();
+    let mut names: Vec<(String, String)> = Vec::new();
+
+    for (index, t) in tokens.iter().enumerate() {
+        let previous = if index > 0 { tokens.get(index - 1) } else { None };
+        let after_dot = previous.map_or(false, |p| p.is("."));
+        let after_path = previous.map_or(false, |p| p.is("::"));
+        let replacement = if t.is("{") {
+            scope.increase_depth();
+            None
+        } else if t.is("}") {
+            scope.decrease_depth();
+            None
+        } else if let Some(offset) = bindings.get(&index) {
+            for _ in 0..*offset { scope.increase_depth(); }
+            for _ in *offset..0 { scope.decrease_depth(); }
+            scope.add_variable(String::from_str(t.text));
+            let variable = scope.get_variable(String::from_str(t.text));
+            for _ in 0..*offset { scope.decrease_depth(); }
+            for _ in *offset..0 { scope.increase_depth(); }
+            variable
+        } else if t.kind == Kind::Lifetime {
+            items.generics.get(t.text).cloned()
+        } else if t.kind == Kind::Ident {
+            // Fields are only renamed where they are declared, initialized or accessed.
+            let field_position = after_dot || (tokens.get(index + 1).map_or(false, |n| n.is(":")) &&
+                                               previous.map_or(false, |p| p.is("{") || p.is(",") || p.is("]")));
+            match items.fields.get(t.text) {
+                Some(field) if field_position => Some(field.clone()),
+                // Fields and paths, `a.b` and `a::b`, are otherwise never renamed.
+                _ if after_dot || after_path => None,
+                _ => items.generics.get(t.text).cloned()
+                    .or_else(|| scope.get_variable(String::from_str(t.text))),
+            }
+        } else {
+            None
+        };
+
+        match replacement {
+            Some(replacement) => {
+                names.push((replacement.clone(), String::from_str(t.text)));
+                key.push(replacement);
+            },
+            None => key.push(String::from_str(t.text)),
+        }
+    }
+
+    (key.connect(" "), names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_key_rust, normalize_rust};
+
+    #[test]
+    fn renamed_let_matches() {
+        let old = "let count = items.len();\nprintln!(\"{}\", count);\n";
+        let new = "let n = items.len();\nprintln!(\"{}\", n);\n";
+        assert_eq!(format_key_rust(String::from_str(old)), format_key_rust(String::from_str(new)));
+    }
+
+    #[test]
+    fn comments_and_raw_strings_are_left_alone() {
+        let old = "let x = r#\"x \"y\"\"#; // x isn't \"y\"\nx";
+        let new = "let y = r#\"x \"y\"\"#;\ny";
+        assert_eq!(format_key_rust(String::from_str(old)), format_key_rust(String::from_str(new)));
+    }
+
+    #[test]
+    fn renamed_loop_and_closure_bindings_match() {
+        let old = "for item in items.iter() {\n    total += item.map(|x| x * 2);\n}\n";
+        let new = "for it in items.iter() {\n    total += it.map(|y| y * 2);\n}\n";
+        assert_eq!(format_key_rust(String::from_str(old)), format_key_rust(String::from_str(new)));
+    }
+
+    #[test]
+    fn renamed_match_and_fn_bindings_match() {
+        let old = "fn get(map: &Map, name: &str) -> u8 {\n    match map.find(name) {\n        Some(value) => value,\n        None => 0,\n    }\n}\n";
+        let new = "fn get(m: &Map, key: &str) -> u8 {\n    match m.find(key) {\n        Some(v) => v,\n        None => 0,\n    }\n}\n";
+        assert_eq!(format_key_rust(String::from_str(old)), format_key_rust(String::from_str(new)));
+    }
+
+    #[test]
+    fn deep_renames_generics_lifetimes_and_fields() {
+        let old = "struct Wrap<'a, T> {\n    inner: &'a T,\n}\nimpl<'a, T> Wrap<'a, T> {\n    fn get(&self) -> &'a T { self.inner }\n}\n";
+        let new = "struct Wrap<'de, Item> {\n    value: &'de Item,\n}\nimpl<'de, Item> Wrap<'de, Item> {\n    fn get(&self) -> &'de Item { self.value }\n}\n";
+        let (old_key, _) = normalize_rust(old, true);
+        let (new_key, names) = normalize_rust(new, true);
+        assert_eq!(old_key, new_key);
+        assert!(names.contains(&(String::from_str("g1"), String::from_str("Item"))));
+        assert!(format_key_rust(String::from_str(old)) != format_key_rust(String::from_str(new)));
+    }
+
+    #[test]
+    fn struct_pattern_bindings_match() {
+        let old = "match x { Foo { a, b } => a + b }";
+        let new = "match x { Foo { c, d } => c + d }";
+        assert_eq!(format_key_rust(String::from_str(old)), format_key_rust(String::from_str(new)));
+        let old = "let Point { x: a, y: b } = p;\na + b";
+        let new = "let Point { x: c, y: d } = p;\nc + d";
+        assert_eq!(format_key_rust(String::from_str(old)), format_key_rust(String::from_str(new)));
+    }
+
+    #[test]
+    fn variants_are_not_bindings() {
+        let old = "match x { None => 1, Some(_) => 2 }";
+        let new = "match x { Empty => 1, Some(_) => 2 }";
+        assert!(format_key_rust(String::from_str(old)) != format_key_rust(String::from_str(new)));
+    }
+}
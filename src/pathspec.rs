@@ -0,0 +1,90 @@
+// Include and exclude patterns for the files to analyse, eg. `src` or
+// `*.lock`. Like git's pathspecs, `*` matches across directories, and a
+// pattern without any wildcards also matches everything inside of it.
+
+#[derive(Debug, Clone, Default)]
+pub struct Pathspec {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+/// Matches `path` against a pattern with `*` and `?` wildcards. Unless
+/// `cross_directories` is set, only `**` matches a `/`.
+pub fn glob(pattern: &[char], path: &[char], cross_directories: bool) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some(&'*') => {
+            let double = pattern.get(1) == Some(&'*');
+            let rest = if double { &pattern[2..] } else { &pattern[1..] };
+            // How far the wildcard can reach.
+            let reach = if double || cross_directories {
+                path.len()
+            } else {
+                path.iter().position(|c| *c == '/').unwrap_or(path.len())
+            };
+            (0..reach + 1).any(|skip| glob(rest, &path[skip..], cross_directories))
+        },
+        Some(&'?') => !path.is_empty() && path[0] != '/' && glob(&pattern[1..], &path[1..], cross_directories),
+        Some(c) => path.first() == Some(c) && glob(&pattern[1..], &path[1..], cross_directories),
+    }
+}
+
+fn matches(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_right_matches('/');
+    if path == pattern || path.starts_with(&format!("{}/", pattern)) { return true; }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    glob(&pattern, &path, true)
+}
+
+impl Pathspec {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether `path` should be analysed. With no includes, everything which
+    /// isn't excluded is.
+    pub fn matches(&self, path: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|pattern| matches(pattern, path));
+        included && !self.exclude.iter().any(|pattern| matches(pattern, path))
+    }
+
+    /// The patterns in the form libgit2 takes, excludes are negated with `!`.
+    pub fn to_git(&self) -> Vec<String> {
+        let mut patterns = if self.include.is_empty() {
+            vec![String::from_str("*")]
+        } else {
+            self.include.clone()
+        };
+        patterns.extend(self.exclude.iter().map(|pattern| format!("!{}", pattern)));
+        patterns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pathspec;
+
+    #[test]
+    fn include_and_exclude() {
+        let paths = Pathspec {
+            include: vec![String::from_str("src")],
+            exclude: vec![String::from_str("*.pb.rs"), String::from_str("src/vendor")],
+        };
+        assert!(paths.matches("src/main.rs"));
+        assert!(paths.matches("src/a/b.rs"));
+        assert!(!paths.matches("README.md"));
+        assert!(!paths.matches("src/proto/message.pb.rs"));
+        assert!(!paths.matches("src/vendor/lib.rs"));
+    }
+
+    #[test]
+    fn exclude_only() {
+        let paths = Pathspec {
+            include: Vec::new(),
+            exclude: vec![String::from_str("Cargo.lock")],
+        };
+        assert!(paths.matches("src/main.rs"));
+        assert!(!paths.matches("Cargo.lock"));
+    }
+}
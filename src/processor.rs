@@ -1,322 +1,859 @@
-use {git2, rustc_serialize};
-use git2::{Repository, Commit, Diff, DiffFormat, Oid, DiffDelta};
-use std::collections::HashMap;
+use {git2, num_cpus, rustc_serialize};
+use git2::{Repository, Commit, Delta, Diff, DiffFindOptions, DiffFormat, DiffOptions, ObjectType, Oid, Revwalk, Tree};
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
-use std::str;
+use std::hash::{Hash, Hasher, SipHasher};
+use std::path::Path;
+use std::rc::Rc;
+use std::str::{self, FromStr};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
 
-use scope;
+use revisions::Revisions;
+use pathspec::Pathspec;
+use attributes::Attributes;
+use cache::Cache;
+use normalizer::{KeyNormalizer, Registry, has_extension};
+use normalizer::rust::items::{self, Item};
+use similarity::{self, Shingles};
 
-pub fn commits(repo: Repository, old_id: Oid, new_id: Oid) -> Result<OutputSet, git2::Error> {
+/// Settings for an analysis.
+#[derive(Clone)]
+pub struct Options {
+    pub registry: Registry,
+    /// How similar two hunks must be, from `0.0` to `1.0`, to be considered a
+    /// move. At `1.0` only hunks with identical keys are.
+    pub threshold: f64,
+    /// Also look for added code which duplicates unchanged code in the old tree.
+    pub copies: bool,
+    /// How long unmatched deletions are kept around while walking a repo, so
+    /// code re-added by a later commit is still found as a move.
+    pub window: Option<Window>,
+    /// How similar, in percent, a file must be to a deleted one to be a rename.
+    pub rename_threshold: u16,
+    /// How similar, in percent, a file must be to another one to be a copy.
+    pub copy_threshold: u16,
+    /// Which parents of a merge it is compared against when walking a repo.
+    pub parents: Parents,
+    /// Which files are analysed. Others don't count as added or deleted lines.
+    pub paths: Pathspec,
+    /// Also analyse generated files, which are otherwise skipped.
+    pub generated: bool,
+    pub diff: DiffSettings,
+    /// How many commits are analysed at once when walking a repo.
+    pub jobs: usize,
+    /// Where results are kept between runs, if anywhere.
+    pub cache: Option<Cache>,
+}
+
+// Bump when a change to the analysis would make cached results wrong.
+const CACHE_VERSION: u32 = 2;
+
+impl Options {
+    /// Identifies the version of transit and the options which shape the
+    /// result of comparing two commits, so results can be cached.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = SipHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        CACHE_VERSION.hash(&mut hasher);
+        self.registry.names().hash(&mut hasher);
+        // Floats can't be hashed, but their output can.
+        format!("{:?}", (self.threshold, self.copies, self.rename_threshold, self.copy_threshold,
+                         &self.paths, self.generated, self.diff)).hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+/// How git diffs each pair of commits. Recorded in every `OutputSet`.
+#[derive(Debug, PartialEq, Clone, Copy, RustcEncodable, RustcDecodable)]
+pub struct DiffSettings {
+    pub algorithm: Algorithm,
+    /// Lines of context around each hunk, which can join nearby hunks.
+    pub context_lines: u32,
+    pub whitespace: Whitespace,
+    /// Leave out hunks which only add or remove blank lines.
+    pub ignore_blank_lines: bool,
+}
+
+/// libgit2 has no histogram diff, patience is the closest.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, RustcEncodable, RustcDecodable)]
+pub enum Algorithm {
+    Myers,
+    /// Myers, but spending extra time to find the smallest diff.
+    Minimal,
+    Patience,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Algorithm, String> {
+        match string {
+            "myers" => Ok(Algorithm::Myers),
+            "minimal" => Ok(Algorithm::Minimal),
+            "patience" => Ok(Algorithm::Patience),
+            "histogram" => Err(String::from_str("libgit2 doesn't support histogram diffs, try patience.")),
+            _ => Err(format!("Unknown diff algorithm `{}`, expected myers, minimal or patience.", string)),
+        }
+    }
+}
+
+/// Which whitespace changes are ignored.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, RustcEncodable, RustcDecodable)]
+pub enum Whitespace {
+    /// None, like `git diff`.
+    Keep,
+    /// All of them, like `git diff -w`.
+    All,
+    /// Changes in the amount of whitespace, like `git diff -b`.
+    Change,
+    /// Only at the end of lines.
+    Eol,
+}
+
+impl FromStr for Whitespace {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Whitespace, String> {
+        match string {
+            "keep" => Ok(Whitespace::Keep),
+            "all" => Ok(Whitespace::All),
+            "change" => Ok(Whitespace::Change),
+            "eol" => Ok(Whitespace::Eol),
+            _ => Err(format!("Unknown whitespace mode `{}`, expected keep, all, change or eol.", string)),
+        }
+    }
+}
+
+impl Default for DiffSettings {
+    fn default() -> DiffSettings {
+        DiffSettings {
+            algorithm: Algorithm::Myers,
+            context_lines: 3,
+            whitespace: Whitespace::Keep,
+            ignore_blank_lines: false,
+        }
+    }
+}
+
+impl DiffSettings {
+    fn to_git(&self) -> DiffOptions {
+        let mut diff_options = DiffOptions::new();
+        diff_options.context_lines(self.context_lines);
+        match self.algorithm {
+            Algorithm::Myers => (),
+            Algorithm::Minimal => { diff_options.minimal(true); },
+            Algorithm::Patience => { diff_options.patience(true); },
+        }
+        match self.whitespace {
+            Whitespace::Keep => (),
+            Whitespace::All => { diff_options.ignore_whitespace(true); },
+            Whitespace::Change => { diff_options.ignore_whitespace_change(true); },
+            Whitespace::Eol => { diff_options.ignore_whitespace_eol(true); },
+        }
+        diff_options
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Parents {
+    /// Only the first parent, side branches are skipped.
+    First,
+    /// Every parent, giving one `OutputSet` per parent.
+    Every,
+    /// Only what a merge changed compared to all of its parents, eg. an
+    /// "evil merge" which does more than join branches.
+    Combined,
+}
+
+impl FromStr for Parents {
+    type Err = String;
+    fn from_str(string: &str) -> Result<Parents, String> {
+        match string {
+            "first" => Ok(Parents::First),
+            "every" => Ok(Parents::Every),
+            "combined" => Ok(Parents::Combined),
+            _ => Err(format!("Unknown parents mode `{}`, expected first, every or combined.", string)),
+        }
+    }
+}
+
+/// A span of history. Code which a later commit adds back within it is a
+/// move, but it is still counted as deleted by the earlier commit, whose
+/// `OutputSet` may already be out by the time the later one is walked.
+#[derive(Debug, Clone, Copy)]
+pub enum Window {
+    Commits(usize),
+    Days(u32),
+}
+
+impl Window {
+    // Whether something `commits` commits and `seconds` seconds old is inside of the window.
+    fn contains(&self, commits: usize, seconds: i64) -> bool {
+        match *self {
+            Window::Commits(limit) => commits <= limit,
+            Window::Days(days) => seconds.abs() <= days as i64 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            registry: Registry::default(),
+            threshold: 1.0,
+            copies: false,
+            window: None,
+            // The same as git's defaults.
+            rename_threshold: 50,
+            copy_threshold: 50,
+            parents: Parents::First,
+            paths: Pathspec::default(),
+            generated: false,
+            diff: DiffSettings::default(),
+            jobs: num_cpus::get(),
+            cache: None,
+        }
+    }
+}
+
+pub fn commits(repo: Repository, revisions: &Revisions, options: &Options) -> Result<OutputSet, git2::Error> {
     // Compare a specific commit pair.
+    let (old_id, new_id) = match revisions.endpoints() {
+        Some(endpoints) => endpoints,
+        None => return Err(git2::Error::from_str("Expected exactly one old and one new commit.")),
+    };
     let old = repo.find_commit(old_id);
     let new = repo.find_commit(new_id);
     match (old, new) {
         (Ok(old_commit), Ok(new_commit)) => {
-            let (old_tree, new_tree) = (try!(old_commit.tree()), try!(new_commit.tree()));
-            let diff = try!(Diff::tree_to_tree(&repo, Some(&old_tree), Some(&new_tree), None));
-            let mut adds_deletes = find_additions_and_deletions(diff);
-            let (adds, deletes) = adds_deletes.iter().fold((0,0), |acc, item| {
-                match item.state {
-                    FoundState::Added=> (acc.0 + item.line_count, acc.1),
-                    FoundState::Deleted => (acc.0 + item.line_count, acc.1),
-                }
-            });
-            let moves = try!(find_moves(&mut adds_deletes));
-            let moved_lines = moves.iter().fold(0, |acc, item| acc + item.num_lines);
-            Ok(OutputSet {
-                old: TransitOid(old_id),
-                old_time: old_commit.time().seconds(), // Seconds from Epoch
-                new: TransitOid(new_id),
-                new_time: new_commit.time().seconds(), // Seconds from Epoch
-                outputs: moves,
-                added_lines: adds - moved_lines,
-                deleted_lines: deletes - moved_lines,
-            })
+            let (set, _) = try!(analyse_cached(&Analyser::new(&repo, options), &old_commit, &new_commit));
+            Ok(set)
         },
         _ => Err(git2::Error::from_str("Commit IDs were not valid.")),
     }
 }
 
-pub fn repo(repo: Repository) -> Result<Vec<OutputSet>, git2::Error> {
-    // Pull up the revwalk.
-    // Revwalk.
-    let mut revwalk = try!(repo.revwalk());
-    // Setup some options.
-    revwalk.simplify_first_parent(); // TODO: Maybe remove?
-    let mut flags = git2::Sort::empty();
-    flags.insert(git2::SORT_TIME);
-    flags.insert(git2::SORT_TOPOLOGICAL);
-    revwalk.set_sorting(flags);
-    // Push HEAD to the revwalk.
-    try!(revwalk.push_head());
-    // We sadly must collect here to use `.windows()`
-    let history = revwalk.collect::<Vec<Oid>>();
-    let mut output = Vec::with_capacity(history.len());
-    // Walk through each pair of commits.
-    for pair in history.windows(2) {
-        let (old_id, new_id) = (pair[0], pair[1]);
-        let (old_commit, new_commit) = (try!(repo.find_commit(old_id)), try!(repo.find_commit(new_id)));
-        let (old_tree, new_tree) = (try!(old_commit.tree()), try!(new_commit.tree()));
-        let diff = try!(Diff::tree_to_tree(&repo, Some(&old_tree), Some(&new_tree), None));
-        let mut adds_deletes = find_additions_and_deletions(diff);
-        let (adds, deletes) = adds_deletes.iter().fold((0,0), |acc, item| {
-            match item.state {
-                FoundState::Added => (acc.0 + item.line_count, acc.1),
-                FoundState::Deleted => (acc.0, acc.1 + item.line_count),
-            }
-        });
-        let moves = try!(find_moves(&mut adds_deletes));
-        let moved_lines = moves.iter().fold(0, |acc, item| acc + item.num_lines);
-        let out = OutputSet {
-            old: TransitOid(old_id),
-            old_time: old_commit.time().seconds(), // Seconds from Epoch
-            new: TransitOid(new_id),
-            new_time: new_commit.time().seconds(), // Seconds from Epoch
-            outputs: moves,
-            added_lines: adds - moved_lines,
-            deleted_lines: deletes - moved_lines,
-        };
-        output.push(out);
-    }
-    Ok(output)
+pub fn repo(repo: Repository, revisions: &Revisions, options: &Options) -> Result<Vec<OutputSet>, git2::Error> {
+    let walk = try!(RepoWalk::new(&repo, revisions, options));
+    walk.collect()
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum FoundState {
-    Added, Deleted
+/// Walks the repo like `repo`, but gives each `OutputSet` to `each` as soon as
+/// it is found, oldest first, instead of collecting them. See `RepoWalk` for
+/// what happens before the first one.
+pub fn repo_each<F>(repo: Repository, revisions: &Revisions, options: &Options,
+                    mut each: F) -> Result<(), git2::Error> where F: FnMut(OutputSet) {
+    for set in try!(RepoWalk::new(&repo, revisions, options)) {
+        each(try!(set));
+    }
+    Ok(())
 }
 
-fn format_key(key: String) -> String {
-    let remove_whitespace = regex!(r"\s{2,}"); // 2 or more whitespaces
-    let trim = regex!(r"^[\s]+|[\s]+$");
-    let result = remove_whitespace.replace_all(&key[..], "");
-    trim.replace_all(&result[..], "")
+// How many commits each thread may be handed before the results of the
+// first of them are needed.
+const AHEAD: usize = 4;
+
+/// A walk through a repo which only analyses commits as they are asked for,
+/// so it can be stopped early. The `OutputSet`s are oldest first, and the walk
+/// ends after the first error.
+///
+/// Being oldest first, libgit2 has to walk through every commit in
+/// `revisions` before it knows which is the first one, so that happens before
+/// the first result. Only the commits are read then, none are analysed.
+pub struct RepoWalk<'a> {
+    revwalk: Revwalk<'a>,
+    revisions: &'a Revisions,
+    options: &'a Options,
+    // Analyses the commits itself when there is only one job.
+    analyser: Analyser<'a>,
+    workers: Option<Workers>,
+    // How many commits may be handed to the workers ahead of the next one.
+    ahead: usize,
+    // How many commits have been taken from the revwalk, and whether it ran out.
+    taken: usize,
+    walked: bool,
+    // Results which arrived before those of the commits ahead of them.
+    waiting: HashMap<usize, Result<Analysed, git2::Error>>,
+    // How many commits have been analysed.
+    position: usize,
+    // Deletions which may still be re-added within the window.
+    pending: Vec<Pending>,
+    ready: VecDeque<OutputSet>,
+    done: bool,
 }
 
-fn is_rust_punctuation_char(c: char) -> bool {
-    match c {
-        '{' | '}' | '(' | ')' | '[' | ']' | '<' | '>' |
-        '.' | ',' | ';' | ':' | '!' | '#' | ' ' | '-' => true,
-        _ => false
+impl<'a> RepoWalk<'a> {
+    pub fn new(repo: &'a Repository, revisions: &'a Revisions,
+               options: &'a Options) -> Result<RepoWalk<'a>, git2::Error> {
+        // Pull up the revwalk.
+        let mut revwalk = try!(repo.revwalk());
+        // Setup some options.
+        // Side branches are only walked if their commits are compared against.
+        if options.parents == Parents::First {
+            revwalk.simplify_first_parent();
+        }
+        let mut flags = git2::Sort::empty();
+        flags.insert(git2::SORT_TIME);
+        flags.insert(git2::SORT_TOPOLOGICAL);
+        // Oldest first, so deletions are seen before the additions they turn into.
+        flags.insert(git2::SORT_REVERSE);
+        revwalk.set_sorting(flags);
+        try!(revisions.push_onto(&mut revwalk));
+        // More threads than cores only cost memory.
+        let jobs = cmp::min(cmp::max(options.jobs, 1), num_cpus::get());
+        let analyser = Analyser::new(repo, options);
+        let workers = if jobs > 1 {
+            Some(Workers::start(repo.path(), jobs, revisions, options, &analyser.fingerprint))
+        } else {
+            None
+        };
+        Ok(RepoWalk {
+            revwalk: revwalk,
+            revisions: revisions,
+            options: options,
+            analyser: analyser,
+            workers: workers,
+            ahead: jobs.saturating_mul(AHEAD),
+            taken: 0,
+            walked: false,
+            waiting: HashMap::new(),
+            position: 0,
+            pending: Vec::new(),
+            ready: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    // The results for the next commit, or `None` once every commit is done.
+    fn analyse_next(&mut self) -> Result<Option<Analysed>, git2::Error> {
+        let workers = match self.workers {
+            Some(ref workers) => workers,
+            None => return match self.revwalk.next() {
+                Some(id) => analyse_commit(&self.analyser, id, self.revisions).map(Some),
+                None => Ok(None),
+            },
+        };
+
+        // Keeps every worker busy, without walking further ahead than that takes.
+        while !self.walked && self.taken - self.position < self.ahead {
+            match self.revwalk.next() {
+                Some(id) => {
+                    // Workers only stop early once the walk is dropped.
+                    workers.commits.send((self.taken, id)).ok();
+                    self.taken += 1;
+                },
+                None => self.walked = true,
+            }
+        }
+        if self.position == self.taken { return Ok(None); }
+
+        // Results arrive in any order, those ahead of the next one wait here.
+        while !self.waiting.contains_key(&self.position) {
+            match workers.results.recv() {
+                Ok((position, result)) => { self.waiting.insert(position, result); },
+                Err(_) => return Err(git2::Error::from_str("Every worker thread has stopped.")),
+            }
+        }
+        self.waiting.remove(&self.position).unwrap().map(Some)
+    }
+
+    // Moves across commits depend on the commits before, so are found in order.
+    fn queue(&mut self, results: Analysed) {
+        let position = self.position;
+        self.position += 1;
+        for (mut set, unmatched) in results.into_iter() {
+            if let Some(window) = self.options.window {
+                self.pending.retain(|p| window.contains(position - p.position, set.new_time - p.time));
+                match_pending(&mut set, &mut self.pending, &unmatched);
+                for found in unmatched.into_iter().filter(|f| f.state == FoundState::Deleted) {
+                    self.pending.push(Pending {
+                        commit: set.new,
+                        position: position,
+                        time: set.new_time,
+                        found: found,
+                    });
+                }
+            }
+            self.ready.push_back(set);
+        }
     }
 }
 
-// Tokenizes rust syntax into variables names.
-// Only variables names are guaranteed to be proper tokens,
-// other symbols like '->' will not be properly parsed.
-fn tokenize_rust_variables(str : String) -> Vec<String> {
+impl<'a> Iterator for RepoWalk<'a> {
+    type Item = Result<OutputSet, git2::Error>;
 
-    #[derive(Debug, PartialEq, Eq)]
-    enum State {
-        Start, Ident, Other, String
+    fn next(&mut self) -> Option<Result<OutputSet, git2::Error>> {
+        // Commits outside of `since` and `until` have no results, so this may
+        // take more than one commit.
+        while self.ready.is_empty() {
+            if self.done { return None; }
+            match self.analyse_next() {
+                Ok(Some(results)) => self.queue(results),
+                Ok(None) => self.done = true,
+                Err(error) => {
+                    self.done = true;
+                    return Some(Err(error));
+                },
+            }
+        }
+        self.ready.pop_front().map(Ok)
     }
+}
 
-    let mut state = State::Start;
-    let mut token_vec: Vec<String> = Vec::new();
-    let mut token = String::new();
+// Threads which each analyse the commits sent to them with their own handle to
+// the repo, for as long as a walk lasts. Results are sent back in whatever
+// order they are done in. The threads own copies of the settings, so once the
+// walk is gone they finish the commit they are on and stop by themselves.
+struct Workers {
+    commits: Sender<(usize, Oid)>,
+    results: Receiver<(usize, Result<Analysed, git2::Error>)>,
+}
 
-    for c in str.chars() {
-        match state {
-            State::Start => {
-                token.push(c.clone());
-                if is_rust_punctuation_char(c) {
-                    state = State::Other;
-                } else if c == '"' {
-                    state = State::String;
-                } else {
-                    state = State::Ident;
-                }
-            },
-            State::Ident => {
-                if is_rust_punctuation_char(c) {
-                    token_vec.push(token);
-                    token = String::new();
-                    token.push(c.clone());
-                    state = State::Other;
-                } else if c == '"' {
-                    token_vec.push(token);
-                    token = String::new();
-                    token.push(c.clone());
-                    state = State::String;
-                } else {
-                    token.push(c.clone());
-                    state = State::Ident;
-                }
-            },
-            State::String => {
-                if c == '"' {
-                    if let Some(last) = token.pop() {
-                        if last == '\\' {
-                            token.push(last);
-                            token.push(c.clone());
-                            state = State::String;
-                        } else {
-                            token.push(last);
-                            token.push(c.clone());
-                            token_vec.push(token);
-                            token = String::new();
-                            state = State::Start; // TODO??
-                        }
-                    } else {
-                        unreachable!("Parsing a string. Found an ending quote but token is otherwise empty.");
-                    }
-                } else {
-                    token.push(c.clone());
-                    state = State::String;
-                }
-            },
-            State::Other => {
-                if is_rust_punctuation_char(c) {
-/*
-                    // TODO Ideally :: and -> would not be separate tokens.
-                    if let Some(last) = token.pop() {
-                        if last == ':' && c == ':' {
-                            token.push(last);
-                            token.push(c.clone());
-                            token_vec.push(token);
-                            token = String::new();
-                        } else if last == '-' && c == '>' {
-                            token.push(last);
-                            token.push(c.clone());
-                            token_vec.push(token);
-                            token = String::new();
-                        } else {
-                            token_vec.push(token);
-                            token = String::new();
-                            token.push(c.clone());
+impl Workers {
+    fn start(path: &Path, jobs: usize, revisions: &Revisions, options: &Options,
+             fingerprint: &Option<String>) -> Workers {
+        let (commits, queue) = mpsc::channel::<(usize, Oid)>();
+        let queue = Arc::new(Mutex::new(queue));
+        let (sender, results) = mpsc::channel();
+        let (revisions, options) = (Arc::new(revisions.clone()), Arc::new(options.clone()));
+        for _ in 0..jobs {
+            let (queue, sender, fingerprint) = (queue.clone(), sender.clone(), fingerprint.clone());
+            let (path, revisions, options) = (path.to_path_buf(), revisions.clone(), options.clone());
+            thread::spawn(move || {
+                let repo = match Repository::open(&path) {
+                    Ok(repo) => repo,
+                    Err(error) => {
+                        // Fails the first commit it is given, which ends the walk there.
+                        if let Ok((position, _)) = queue.lock().unwrap().recv() {
+                            sender.send((position, Err(error))).ok();
                         }
-                    } else {
-*/
-                        token_vec.push(token);
-                        token = String::new();
-                        token.push(c.clone());
-                  //  }
-                    state = State::Other;
-                } else if c == '"' {
-                    token_vec.push(token);
-                    token = String::new();
-                    token.push(c.clone());
-                    state = State::String;
-                } else {
-                    token_vec.push(token);
-                    token = String::new();
-                    token.push(c.clone());
-                    state = State::Ident;
+                        return;
+                    },
+                };
+                let analyser = Analyser::with_fingerprint(&repo, &options, fingerprint);
+                loop {
+                    // The lock is only held while waiting for a commit.
+                    let next = queue.lock().unwrap().recv();
+                    let (position, id) = match next {
+                        Ok(next) => next,
+                        Err(_) => break,
+                    };
+                    let result = {
+                        let _working = Working { position: position, sender: &sender };
+                        analyse_commit(&analyser, id, &revisions)
+                    };
+                    if sender.send((position, result)).is_err() { break; }
                 }
-            },
+            });
+        }
+        Workers {
+            commits: commits,
+            results: results,
         }
     }
+}
+
+// Stands for a commit a worker is analysing. If the worker panics, this fails
+// the commit, as otherwise the walk would wait for its results forever.
+struct Working<'a> {
+    position: usize,
+    sender: &'a Sender<(usize, Result<Analysed, git2::Error>)>,
+}
 
-    if !token.is_empty() {
-        token_vec.push(token);
+impl<'a> Drop for Working<'a> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let error = git2::Error::from_str("A worker thread panicked.");
+            self.sender.send((self.position, Err(error))).ok();
+        }
     }
+}
+
+type Analysed = Vec<(OutputSet, Vec<Found>)>;
 
-    token_vec
+// What is needed to analyse pairs of commits, kept for as long as a thread
+// has commits to analyse.
+struct Analyser<'a> {
+    repo: &'a Repository,
+    options: &'a Options,
+    // `options.fingerprint()`, if there is a cache to use it with.
+    fingerprint: Option<String>,
+    // The old tree indexed last for finding copies, by id and where each of
+    // its trees is. Most trees don't change from one commit to the next.
+    trees: RefCell<TreeCache>,
 }
 
-// TODO This function assumes it is only parse a single function (or a portion
-//      of a single function.
-fn format_key_rust(original_string: String) -> String {
+impl<'a> Analyser<'a> {
+    fn new(repo: &'a Repository, options: &'a Options) -> Analyser<'a> {
+        Analyser::with_fingerprint(repo, options, options.cache.as_ref().map(|_| options.fingerprint()))
+    }
 
-    #[derive(Debug, PartialEq, Eq)]
-    enum State {
-        Other, ReadingLet
+    fn with_fingerprint(repo: &'a Repository, options: &'a Options,
+                        fingerprint: Option<String>) -> Analyser<'a> {
+        Analyser {
+            repo: repo,
+            options: options,
+            fingerprint: fingerprint,
+            trees: RefCell::new(HashMap::new()),
+        }
     }
 
-    let trimmed = format_key(original_string);
-    let tokens = tokenize_rust_variables(trimmed);
+    // The files of `tree` which `options.paths` matches.
+    fn index(&self, tree: &Tree) -> Result<Rc<TreeIndex>, git2::Error> {
+        let mut used = HashMap::new();
+        let index = try!(self.index_tree(tree, "", &mut used));
+        // Only this tree is kept, or a walk would keep every tree it saw.
+        *self.trees.borrow_mut() = used;
+        Ok(index)
+    }
 
-    let mut scope = scope::Scope::new();
-    let mut key = String::new();
-    let mut state = State::Other;
+    fn index_tree(&self, tree: &Tree, prefix: &str, used: &mut TreeCache) -> Result<Rc<TreeIndex>, git2::Error> {
+        let key = (tree.id(), String::from_str(prefix));
+        let cached = self.trees.borrow().get(&key).cloned();
+        if let Some(index) = cached {
+            keep_tree(&self.trees.borrow(), &key, used);
+            return Ok(index);
+        }
+        let mut index = TreeIndex {
+            sources: Vec::new(),
+            subtrees: Vec::new(),
+        };
+        for entry in tree.iter() {
+            let name = match entry.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            let filename = format!("{}{}", prefix, name);
+            match entry.kind() {
+                Some(ObjectType::Tree) => {
+                    let subtree = try!(self.repo.find_tree(entry.id()));
+                    let subprefix = format!("{}/", filename);
+                    let inner = try!(self.index_tree(&subtree, &subprefix, used));
+                    index.sources.extend(inner.sources.iter().cloned());
+                    index.subtrees.push((subtree.id(), subprefix));
+                },
+                Some(ObjectType::Blob) if self.options.paths.matches(&filename) => {
+                    let blob = try!(self.repo.find_blob(entry.id()));
+                    // Binary files can't be copied from in any interesting way.
+                    let text = match str::from_utf8(blob.content()) {
+                        Ok(text) => text,
+                        Err(_) => continue,
+                    };
+                    let path = Path::new(&filename);
+                    let lines = normalize_lines(self.options.registry.find(path, path), text);
+                    let hashes: Vec<u64> = lines.iter().map(|line| line.hash).collect();
+                    index.sources.push(Rc::new(Source {
+                        filename: filename.clone(),
+                        windows: similarity::rolling_hashes(&hashes, MIN_RUN),
+                        lines: lines,
+                    }));
+                },
+                _ => (),
+            }
+        }
+        let index = Rc::new(index);
+        used.insert(key, index.clone());
+        Ok(index)
+    }
+}
 
-    for t in tokens {
+fn analyse_commit(analyser: &Analyser, id: Oid, revisions: &Revisions) -> Result<Analysed, git2::Error> {
+    let commit = try!(analyser.repo.find_commit(id));
+    if !revisions.includes_time(commit.time().seconds()) { return Ok(Vec::new()); }
+    analyse_parents(analyser, &commit)
+}
 
-        match state {
-            State::Other => {
-                if t == "{" {
-                    scope.increase_depth();
-                    key = format!("{}{}", key, t);
-                    continue;
-                }
-                if t == "}" {
-                    scope.decrease_depth();
-                    key = format!("{}{}", key, t);
-                    continue;
-                }
-                if t == "let" {
-                    key = format!("{}{}", key, t);
-                    state = State::ReadingLet;
-                    continue;
-                }
-                if let Some(var) = scope.get_variable(t.clone()) {
-                    key = format!("{}{}", key, var);
-                    continue;
-                } else {
-                    key = format!("{}{}", key, t);
-                    continue;
-                }
-            },
+// Compares `commit` against the parents picked by `options.parents`. Root
+// commits have nothing to be compared against.
+fn analyse_parents(analyser: &Analyser, commit: &Commit) -> Result<Analysed, git2::Error> {
+    let parents = try!((0..commit.parent_count())
+        .map(|index| commit.parent(index))
+        .collect::<Result<Vec<Commit>, git2::Error>>());
+    if parents.is_empty() { return Ok(Vec::new()); }
+    match analyser.options.parents {
+        Parents::First => Ok(vec![try!(analyse_cached(analyser, &parents[0], commit))]),
+        Parents::Every => parents.iter().map(|parent| analyse_cached(analyser, parent, commit)).collect(),
+        Parents::Combined if parents.len() == 1 => Ok(vec![try!(analyse_cached(analyser, &parents[0], commit))]),
+        // Side branch code was already seen on the branch.
+        Parents::Combined => Ok(vec![(try!(analyse_combined(analyser, &parents, commit)), Vec::new())]),
+    }
+}
 
-            State::ReadingLet => {
-                match t.as_ref() {
-                    " " | "&" | "mut" | "Some" | "Ok" | "Err" | "(" | ")" | "," => {
-                        key = format!("{}{}", key, t);
-                        continue;
-                    },
-                    ":" | "=" => {
-                        key = format!("{}{}", key, t);
-                        state = State::Other;
-                        continue;
-                    }
-                    _ => {
-                        scope.add_variable(t.clone());
-                        key = format!("{}{}", key, scope.get_variable(t.clone()).unwrap());
-                        continue;
-                    },
-                }
+// A deletion no commit has re-added yet.
+struct Pending {
+    commit: TransitOid,
+    // Which pair of commits it was deleted in.
+    position: usize,
+    time: i64,
+    found: Found,
+}
 
-            },
+// Pairs the unmatched additions of `set` with deletions from earlier commits.
+// Only `set` is changed, the earlier sets may already have been handed out.
+fn match_pending(set: &mut OutputSet, pending: &mut Vec<Pending>, unmatched: &[Found]) {
+    let mut founds: Vec<Found> = pending.iter().map(|p| p.found.clone()).collect();
+    founds.extend(unmatched.iter().filter(|f| f.state == FoundState::Added).cloned());
+    let mut matched: Vec<bool> = founds.iter().map(|_| false).collect();
+    for (deleted, added) in pair_identical(&founds, &mut matched) {
+        set.added_lines = set.added_lines.saturating_sub(founds[added].line_count);
+        set.window_moves.push(WindowMove {
+            old_commit: pending[deleted].commit,
+            new_commit: set.new,
+            output: move_output(&founds[deleted], &founds[added], 1.0),
+        });
+    }
+    let mut index = 0;
+    pending.retain(|_| {
+        index += 1;
+        !matched[index - 1]
+    });
+}
+
+// Like `analyse`, but reuses results kept in `options.cache`.
+fn analyse_cached(analyser: &Analyser, old_commit: &Commit,
+                  new_commit: &Commit) -> Result<(OutputSet, Vec<Found>), git2::Error> {
+    let (cache, fingerprint) = match (&analyser.options.cache, &analyser.fingerprint) {
+        (&Some(ref cache), &Some(ref fingerprint)) => (cache, fingerprint),
+        _ => return analyse(analyser, old_commit, new_commit),
+    };
+    let (old, new) = (old_commit.id(), new_commit.id());
+    if let Some(cached) = cache.load(fingerprint, old, new) {
+        return Ok(cached);
+    }
+    let analysed = try!(analyse(analyser, old_commit, new_commit));
+    // A cache which can't be written to only costs time.
+    cache.store(fingerprint, old, new, &analysed).ok();
+    Ok(analysed)
+}
+
+// Also returns the hunks which weren't part of any move.
+fn analyse(analyser: &Analyser, old_commit: &Commit,
+           new_commit: &Commit) -> Result<(OutputSet, Vec<Found>), git2::Error> {
+    let diffed = try!(diff_commits(analyser, old_commit, new_commit));
+    let parent = (0..new_commit.parent_count())
+        .position(|index| new_commit.parent_id(index).ok() == Some(old_commit.id()));
+    summarise(analyser, old_commit, new_commit, diffed, parent)
+}
+
+// What a diff of two commits holds, before any of it is matched up.
+struct Diffed {
+    // Includes generated files to leave out.
+    paths: Pathspec,
+    founds: Vec<Found>,
+    item_moves: Vec<ItemMove>,
+    file_renames: Vec<FileRename>,
+}
+
+fn diff_commits(analyser: &Analyser, old_commit: &Commit, new_commit: &Commit) -> Result<Diffed, git2::Error> {
+    let (repo, options) = (analyser.repo, analyser.options);
+    let (old_tree, new_tree) = (try!(old_commit.tree()), try!(new_commit.tree()));
+    let mut diff_options = options.diff.to_git();
+    if !options.paths.is_empty() {
+        for pattern in options.paths.to_git().iter() {
+            diff_options.pathspec(&pattern[..]);
+        }
+    }
+    let mut diff = try!(Diff::tree_to_tree(repo, Some(&old_tree), Some(&new_tree), Some(&mut diff_options)));
+    // Otherwise a renamed file is a deletion of the whole file plus an addition
+    // of it, which looks like one huge move.
+    let mut find = DiffFindOptions::new();
+    find.renames(true)
+        .copies(true)
+        .rename_threshold(options.rename_threshold)
+        .copy_threshold(options.copy_threshold);
+    try!(diff.find_similar(Some(&mut find)));
+    let file_renames = try!(find_file_renames(repo, &diff));
+    let paths = if options.generated {
+        options.paths.clone()
+    } else {
+        skip_generated(repo, &old_tree, &new_tree, &diff, &options.paths)
+    };
+    let item_moves = try!(find_item_moves(repo, &diff, &options.registry, &paths));
+    let mut founds = find_additions_and_deletions(diff, &options.registry, &paths);
+    if options.diff.ignore_blank_lines {
+        founds.retain(|found| !found.blank);
+    }
+    Ok(Diffed {
+        paths: paths,
+        founds: founds,
+        item_moves: item_moves,
+        file_renames: file_renames,
+    })
+}
+
+// Matches up the hunks of a diff into moves and copies.
+fn summarise(analyser: &Analyser, old_commit: &Commit, new_commit: &Commit, diffed: Diffed,
+             parent: Option<usize>) -> Result<(OutputSet, Vec<Found>), git2::Error> {
+    let Diffed { paths, founds: mut adds_deletes, item_moves, file_renames } = diffed;
+    let (adds, deletes) = adds_deletes.iter().fold((0,0), |acc, item| {
+        match item.state {
+            FoundState::Added => (acc.0 + item.line_count, acc.1),
+            FoundState::Deleted => (acc.0, acc.1 + item.line_count),
+        }
+    });
+    let (moves, leftovers, matched) = try!(find_moves(&mut adds_deletes, analyser.options.threshold));
+    // Code which moved wasn't copied, even if it looks like code left in place.
+    let copies = if analyser.options.copies {
+        try!(find_copies(analyser, &try!(old_commit.tree()), &adds_deletes, &matched, &paths))
+    } else {
+        Vec::new()
+    };
+    let moved_lines = moves.iter().fold(0, |acc, item| acc + item.num_lines);
+    let unmatched = adds_deletes.into_iter().zip(matched.into_iter())
+        .filter(|&(_, matched)| !matched)
+        .map(|(found, _)| found)
+        .collect();
+    let set = OutputSet {
+        diff: analyser.options.diff,
+        old: TransitOid(old_commit.id()),
+        parent: parent,
+        old_time: old_commit.time().seconds(), // Seconds from Epoch
+        new: TransitOid(new_commit.id()),
+        new_time: new_commit.time().seconds(), // Seconds from Epoch
+        outputs: moves,
+        leftovers: leftovers,
+        copies: copies,
+        item_moves: item_moves,
+        window_moves: Vec::new(),
+        file_renames: file_renames,
+        added_lines: adds - moved_lines,
+        deleted_lines: deletes - moved_lines,
+    };
+    Ok((set, unmatched))
+}
 
+// Compares a merge against all of its `parents` at once, like `git diff --cc`.
+// Only lines which were added, or deleted, against every parent are kept, so a
+// clean merge changes nothing. Line numbers differ between parents, so lines
+// are told apart by their path and content. The set is given the line numbers
+// of the first parent, and isn't cached, as it is keyed by pairs of commits.
+fn analyse_combined(analyser: &Analyser, parents: &[Commit], commit: &Commit) -> Result<OutputSet, git2::Error> {
+    let mut diffs = try!(parents.iter()
+        .map(|parent| diff_commits(analyser, parent, commit))
+        .collect::<Result<Vec<Diffed>, git2::Error>>());
+    let mut combined = diffs.remove(0);
+    for other in diffs.iter() {
+        combined.founds = common_lines(&analyser.options.registry, &combined.founds, &other.founds);
+        combined.item_moves.retain(|item_move| other.item_moves.iter().any(|o| o.same_item(item_move)));
+        combined.file_renames.retain(|rename| other.file_renames.contains(rename));
+    }
+    let (set, _) = try!(summarise(analyser, &parents[0], commit, combined, None));
+    Ok(set)
+}
+
+// The parts of `founds` whose lines `other` has as well, in the same file and
+// the same state. Each line of `other` stands in for only one line.
+fn common_lines(registry: &Registry, founds: &[Found], other: &[Found]) -> Vec<Found> {
+    let mut counts: HashMap<(&str, bool, u64), usize> = HashMap::new();
+    for found in other.iter() {
+        for line in found.lines.iter() {
+            let key = (&found.filename[..], found.state == FoundState::Added, line.hash);
+            let count = counts.get(&key).cloned().unwrap_or(0);
+            counts.insert(key, count + 1);
         }
     }
+    let mut common = Vec::new();
+    for found in founds.iter() {
+        let kept: Vec<bool> = found.lines.iter().map(|line| {
+            let key = (&found.filename[..], found.state == FoundState::Added, line.hash);
+            let count = counts.get(&key).cloned().unwrap_or(0);
+            if count > 0 { counts.insert(key, count - 1); }
+            count > 0
+        }).collect();
+        if kept.iter().all(|kept| *kept) {
+            common.push(found.clone());
+            continue;
+        }
+        // Split into runs of kept lines.
+        let mut start = 0;
+        while start < kept.len() {
+            if !kept[start] { start += 1; continue; }
+            let mut end = start;
+            while end < kept.len() && kept[end] { end += 1; }
+            common.push(found.part(registry, start, end));
+            start = end;
+        }
+    }
+    common
+}
 
-    key
+#[derive(Debug, PartialEq, Eq, Clone, Copy, RustcEncodable, RustcDecodable)]
+enum FoundState {
+    Added, Deleted
 }
 
-#[derive(Debug, Clone)]
+// A line of a hunk, normalized on its own so parts of hunks can be compared.
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
+struct Line {
+    key: String,
+    names: Vec<(String, String)>,
+    hash: u64,
+}
+
+#[derive(Debug, Clone, RustcEncodable, RustcDecodable)]
 struct Found {
     filename: String,
     key: String,
+    // `(replacement, original)` for each name the normalizer replaced in `key`.
+    names: Vec<(String, String)>,
     state: FoundState,
     start_position: u32,
     line_count: u32,
+    lines: Vec<Line>,
+    // Only whitespace.
+    blank: bool,
+    // As it was in the diff, to split it up again.
+    text: String,
 }
 
-#[derive(Debug)]
-enum State {
-    Other, Addition, Deletion
-}
-
-fn which_key_format_function(delta : DiffDelta) -> (fn(String) -> String) {
-    let oldpath = delta.old_file().path().unwrap(); // TODO Do additions have old files?
-    let newpath = delta.new_file().path().unwrap(); // TODO Do deletions have new files?
+impl Found {
+    fn new(normalizer: &KeyNormalizer, filename: String, hunk: &str, state: FoundState,
+           start_position: u32, line_count: u32) -> Found {
+        let (key, names) = normalizer.normalize_with_names(String::from_str(hunk));
+        Found {
+            filename: filename,
+            key: key,
+            names: names,
+            state: state,
+            start_position: start_position,
+            line_count: line_count,
+            lines: normalize_lines(normalizer, hunk),
+            blank: hunk.trim().is_empty(),
+            text: String::from_str(hunk),
+        }
+    }
 
-    if oldpath.extension() != newpath.extension() {
-        println!("File extensions are different.");
-        return format_key;
+    // Lines `start..end` of the hunk on their own.
+    fn part(&self, registry: &Registry, start: usize, end: usize) -> Found {
+        let text: String = self.text.lines().skip(start).take(end - start)
+            .map(|line| format!("{}\n", line))
+            .collect::<Vec<String>>()
+            .concat();
+        let path = Path::new(&self.filename);
+        Found::new(registry.find(path, path), self.filename.clone(), &text, self.state,
+                   self.start_position + start as u32, (end - start) as u32)
     }
+}
 
-    if let Some(ext) = oldpath.extension() {
-        if ext == "rs" {
-            return format_key_rust;
+fn normalize_lines(normalizer: &KeyNormalizer, text: &str) -> Vec<Line> {
+    text.lines().map(|line| {
+        let (key, names) = normalizer.normalize_with_names(String::from_str(line));
+        Line {
+            hash: similarity::hash_line(&key),
+            key: key,
+            names: names,
         }
-    }
+    }).collect()
+}
 
-    return format_key;
+#[derive(Debug)]
+enum State {
+    Other, Addition, Deletion
 }
 
-fn find_additions_and_deletions(diff: Diff) -> Vec<Found> {
+fn find_additions_and_deletions(diff: Diff, registry: &Registry, paths: &Pathspec) -> Vec<Found> {
 
     let mut founds: Vec<Found> = Vec::new();
 
@@ -328,6 +865,7 @@ fn find_additions_and_deletions(diff: Diff) -> Vec<Found> {
 
     let mut line_count: u32 = 0;
     let mut start_position: u32 = 0;
+    let mut normalizer: &KeyNormalizer = registry.fallback();
 
     // Read about this function in http://alexcrichton.com/git2-rs/git2/struct.Diff.html#method.print
     // It's a bit weird, but I think it will provide the necessary information.
@@ -352,20 +890,49 @@ fn find_additions_and_deletions(diff: Diff) -> Vec<Found> {
         //dump_diffdelta(&delta);
         //dump_diffhunk(&maybe_hunk.unwrap());
 
-        old_path = match delta.old_file().path()
+        let delta_old_path = match delta.old_file().path()
         .and_then(|x| x.to_str()) {
-            Some(path) => String::from_str(path),
+            Some(path) => path,
             None => return false,
         };
-        new_path = match delta.new_file().path()
+        let delta_new_path = match delta.new_file().path()
         .and_then(|x| x.to_str()) {
-            Some(path) => String::from_str(path),
+            Some(path) => path,
             None => return false,
         };
 
-        let format_key = which_key_format_function(delta);
+        // Lines of filtered out files are treated like context, ending
+        // whatever was being collected from the file before.
+        let excluded = !paths.matches(delta_old_path) && !paths.matches(delta_new_path);
+        if !excluded && (old_path != delta_old_path || new_path != delta_new_path) {
+            // A new file ends whatever was being collected from the file
+            // before, which belongs to that file's paths and normalizer.
+            match state {
+                State::Addition => {
+                    founds.push(Found::new(normalizer, new_path.clone(), &added, FoundState::Added,
+                                           start_position, line_count));
+                    added = String::new();
+                },
+                State::Deletion => {
+                    founds.push(Found::new(normalizer, old_path.clone(), &deleted, FoundState::Deleted,
+                                           start_position, line_count));
+                    deleted = String::new();
+                },
+                _ => (),
+            }
+            state = State::Other;
+            old_path = String::from_str(delta_old_path);
+            new_path = String::from_str(delta_new_path);
+            normalizer = registry.find(Path::new(&old_path), Path::new(&new_path));
+        }
+
+        let origin = if excluded { ' ' } else { line.origin() };
+        // The source of a copy is left in place, so the lines the copy is
+        // missing weren't deleted.
+        let copied = match delta.status() { Delta::Copied => true, _ => false };
+        let origin = if copied && (origin == '-' || origin == '<') { ' ' } else { origin };
 
-        match line.origin() {
+        match origin {
             // Additions
             '+' | '>' => {
                 // If we attempt to unwrap and get `InvalidBytes(_)` it's probably just junk.
@@ -375,15 +942,10 @@ fn find_additions_and_deletions(diff: Diff) -> Vec<Found> {
 
                 match state {
                     State::Deletion => {
-                        founds.push(Found {
-                            filename: old_path.clone(),
-                            key: format_key(deleted.clone()),
-                            state: FoundState::Deleted,
-                            start_position: start_position,
-                            line_count: line_count,
-                        });
+                        founds.push(Found::new(normalizer, old_path.clone(), &deleted, FoundState::Deleted,
+                                               start_position, line_count));
                         deleted = String::new();
-                        line_count = 0;
+                        line_count = 1;
                         start_position = match line.new_lineno() {
                             Some(lineno) => lineno,
                             None => return false, // Can't be a move if deleted.
@@ -413,15 +975,10 @@ fn find_additions_and_deletions(diff: Diff) -> Vec<Found> {
 
                 match state {
                     State::Addition => {
-                        founds.push(Found {
-                            filename: new_path.clone(),
-                            key: format_key(added.clone()),
-                            state: FoundState::Added,
-                            start_position: start_position,
-                            line_count: line_count,
-                        });
+                        founds.push(Found::new(normalizer, new_path.clone(), &added, FoundState::Added,
+                                               start_position, line_count));
                         added = String::new();
-                        line_count = 0;
+                        line_count = 1;
                         start_position = match line.old_lineno() {
                             Some(lineno) => lineno,
                             None => return false, // Can't be a move if deleted.
@@ -446,23 +1003,13 @@ fn find_additions_and_deletions(diff: Diff) -> Vec<Found> {
             _         => {
                 match state {
                     State::Addition => {
-                        founds.push(Found {
-                            filename: new_path.clone(),
-                            key: format_key(added.clone()),
-                            state: FoundState::Added,
-                            start_position: start_position,
-                            line_count: line_count,
-                        });
+                        founds.push(Found::new(normalizer, new_path.clone(), &added, FoundState::Added,
+                                               start_position, line_count));
                         added = String::new();
                     },
                     State::Deletion => {
-                        founds.push(Found {
-                            filename: old_path.clone(),
-                            key: format_key(deleted.clone()),
-                            state: FoundState::Deleted,
-                            start_position: start_position,
-                            line_count: line_count,
-                        });
+                        founds.push(Found::new(normalizer, old_path.clone(), &deleted, FoundState::Deleted,
+                                               start_position, line_count));
                         deleted = String::new();
                     },
                     _ => (),
@@ -478,24 +1025,14 @@ fn find_additions_and_deletions(diff: Diff) -> Vec<Found> {
     match state {
         State::Addition => {
             if added.len() > 0 {
-                founds.push(Found {
-                    filename: new_path.clone(),
-                    key: format_key(added.clone()),
-                    state: FoundState::Added,
-                    start_position: start_position,
-                    line_count: line_count,
-                });
+                founds.push(Found::new(normalizer, new_path.clone(), &added, FoundState::Added,
+                                       start_position, line_count));
             }
         },
         State::Deletion => {
             if deleted.len() > 0 {
-                founds.push(Found {
-                    filename: old_path.clone(),
-                    key: format_key(deleted.clone()),
-                    state: FoundState::Deleted,
-                    start_position: start_position,
-                    line_count: line_count,
-                });
+                founds.push(Found::new(normalizer, old_path.clone(), &deleted, FoundState::Deleted,
+                                       start_position, line_count));
             }
         },
         _ => (),
@@ -504,75 +1041,717 @@ fn find_additions_and_deletions(diff: Diff) -> Vec<Found> {
     return founds;
 }
 
-fn find_moves(founds: &mut Vec<Found>) -> Result<Vec<Output>, git2::Error> {
-    let mut moves: Vec<Output> = Vec::new();
-    let mut map: HashMap<String, Found> = HashMap::new();
+// Names which stand for the same replacement in both keys, but differ.
+fn find_renames(deleted: &[(String, String)], added: &[(String, String)]) -> Vec<Rename> {
+    let mut originals: HashMap<&str, &str> = HashMap::new();
+    for &(ref replacement, ref original) in deleted.iter() {
+        if !originals.contains_key(&replacement[..]) {
+            originals.insert(&replacement[..], &original[..]);
+        }
+    }
+    let mut renames: Vec<Rename> = Vec::new();
+    for &(ref replacement, ref original) in added.iter() {
+        if let Some(from) = originals.get(&replacement[..]) {
+            let reported = renames.iter().any(|r| r.from == *from && r.to == *original);
+            if *from != &original[..] && !reported {
+                renames.push(Rename {
+                    from: String::from_str(from),
+                    to: original.clone(),
+                });
+            }
+        }
+    }
+    renames
+}
+
+fn move_output(deleted: &Found, added: &Found, similarity: f64) -> Output {
+    Output {
+        old_filename: deleted.filename.clone(),
+        new_filename: added.filename.clone(),
+        origin_line: deleted.start_position,
+        destination_line: added.start_position,
+        // Fuzzy matches may differ in length, only count what both have.
+        num_lines: cmp::min(deleted.line_count, added.line_count),
+        similarity: similarity,
+        renames: find_renames(&deleted.names, &added.names),
+    }
+}
+
+// How far apart two files are, counted in directories to walk through.
+// `src/a/x.rs` and `src/b/y.rs` are 2 apart, files in the same directory 0.
+fn path_distance(a: &str, b: &str) -> usize {
+    let a: Vec<&str> = a.split('/').collect();
+    let b: Vec<&str> = b.split('/').collect();
+    let (a, b) = (&a[..a.len() - 1], &b[..b.len() - 1]);
+    let common = a.iter().zip(b.iter()).take_while(|&(x, y)| x == y).count();
+    (a.len() - common) + (b.len() - common)
+}
+
+// Whether a key is only punctuation, like a lone `}`, which says nothing
+// about where code went.
+fn is_trivial(key: &str) -> bool {
+    !key.chars().any(|c| c.is_alphanumeric())
+}
+
+// Pairs deletions and additions which share a key. Hunks in the same file are
+// preferred, then those in the nearest directory, then the nearest line.
+// Returns `(deleted, added)` indices.
+fn pair_identical(founds: &[Found], matched: &mut Vec<bool>) -> Vec<(usize, usize)> {
+    let mut groups: Vec<(&str, Vec<usize>, Vec<usize>)> = Vec::new();
+    let mut group_of: HashMap<&str, usize> = HashMap::new();
+    for (index, f) in founds.iter().enumerate() {
+        if is_trivial(&f.key) { continue; }
+        let group = match group_of.get(&f.key[..]) {
+            Some(&group) => group,
+            None => {
+                groups.push((&f.key[..], Vec::new(), Vec::new()));
+                groups.len() - 1
+            },
+        };
+        group_of.insert(&f.key[..], group);
+        match f.state {
+            FoundState::Deleted => groups[group].1.push(index),
+            FoundState::Added => groups[group].2.push(index),
+        }
+    }
+
+    let mut paired = Vec::new();
+    for &(_, ref deletions, ref additions) in groups.iter() {
+        let mut pairs: Vec<((bool, usize, u32), usize, usize)> = Vec::new();
+        for &deleted in deletions.iter() {
+            for &added in additions.iter() {
+                let (old, new) = (&founds[deleted], &founds[added]);
+                let other_file = old.filename != new.filename;
+                let lines = if old.start_position > new.start_position {
+                    old.start_position - new.start_position
+                } else {
+                    new.start_position - old.start_position
+                };
+                pairs.push(((other_file, path_distance(&old.filename, &new.filename), lines), deleted, added));
+            }
+        }
+        // Stable, so equally good pairs keep the order of the diff.
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        for &(_, deleted, added) in pairs.iter() {
+            if matched[deleted] || matched[added] { continue; }
+            matched[deleted] = true;
+            matched[added] = true;
+            paired.push((deleted, added));
+        }
+    }
+    paired
+}
 
-    for f in founds {
-        if f.key.len() == 0 { continue; }
+// Returns the moves, the leftovers, and which of `founds` are part of a move.
+fn find_moves(founds: &mut Vec<Found>, threshold: f64) -> Result<(Vec<Output>, Vec<Leftover>, Vec<bool>), git2::Error> {
+    let mut matched: Vec<bool> = founds.iter().map(|_| false).collect();
+    let mut moves: Vec<Output> = pair_identical(founds, &mut matched).iter()
+        .map(|&(deleted, added)| move_output(&founds[deleted], &founds[added], 1.0))
+        .collect();
 
-        if map.contains_key(&f.key) {
-            let q = map.get(&f.key).unwrap();
+    // Hunks which had a twin on the other side, but one too many of them.
+    let leftovers: Vec<Leftover> = founds.iter().zip(matched.iter())
+        .filter(|&(f, done)| {
+            !*done && !is_trivial(&f.key) && founds.iter().any(|q| q.state != f.state && q.key == f.key)
+        })
+        .map(|(f, _)| Leftover {
+            filename: f.filename.clone(),
+            added: f.state == FoundState::Added,
+            line: f.start_position,
+            num_lines: f.line_count,
+        })
+        .collect();
 
-            // If both states are added, there are no moves.
-            // assert!(f.state != q.state, format!("States {:?}, {:?}. Should be Addition/Deletion ", f.state, q.state));
-            if f.state == q.state {
-                return Ok(Vec::<Output>::new())
+    // Pair what is left with the most similar deletion which is similar enough.
+    if threshold < 1.0 {
+        // Every pair is compared, so each key is only shingled once.
+        let shingles: Vec<Shingles> = founds.iter().map(|f| Shingles::new(&f.key)).collect();
+        for (index, f) in founds.iter().enumerate() {
+            if matched[index] || f.state != FoundState::Added || is_trivial(&f.key) { continue; }
+            let mut best: Option<(usize, f64)> = None;
+            for (q_index, q) in founds.iter().enumerate() {
+                if matched[q_index] || q.state != FoundState::Deleted || is_trivial(&q.key) { continue; }
+                let score = shingles[q_index].similarity(&shingles[index]);
+                if score >= threshold && best.map_or(true, |(_, best_score)| score > best_score) {
+                    best = Some((q_index, score));
+                }
+            }
+            if let Some((q_index, score)) = best {
+                matched[index] = true;
+                matched[q_index] = true;
+                moves.push(move_output(&founds[q_index], f, score));
             }
+        }
+    }
 
-            let output: Output;
+    moves.extend(find_sub_moves(founds, &matched).into_iter());
 
-            match f.state {
-                FoundState::Added => {
-                    output = Output {
-                        old_filename: q.filename.clone(),
-                        new_filename: f.filename.clone(),
-                        origin_line: q.start_position,
-                        destination_line: f.start_position,
-                        num_lines: f.line_count,
-                    };
-                },
-                FoundState::Deleted => {
-                    output = Output {
-                        old_filename: f.filename.clone(),
-                        new_filename: q.filename.clone(),
-                        origin_line: f.start_position,
-                        destination_line: q.start_position,
-                        num_lines: f.line_count,
-                    };
-                },
+    return Ok((moves, leftovers, matched));
+}
+
+// The fewest lines, not counting blank ones, a run needs to be a move on its own.
+const MIN_RUN: usize = 3;
+
+// Lines `old_offset..old_offset + length` of `founds[deleted]` are the same as
+// lines `new_offset..new_offset + length` of `founds[added]`.
+struct Run {
+    deleted: usize,
+    added: usize,
+    old_offset: usize,
+    new_offset: usize,
+    length: usize,
+}
+
+fn window_hashes(found: &Found) -> Vec<u64> {
+    let hashes: Vec<u64> = found.lines.iter().map(|line| line.hash).collect();
+    similarity::rolling_hashes(&hashes, MIN_RUN)
+}
+
+fn run_names(lines: &[Line]) -> Vec<(String, String)> {
+    let mut names = Vec::new();
+    for line in lines.iter() {
+        names.push_all(&line.names);
+    }
+    names
+}
+
+// Moves of part of a hunk, eg. 20 lines carved out of a 200 line deletion.
+// Unmatched hunks are compared line by line, and the longest runs of lines
+// they share are reported first. No line is part of more than one move.
+fn find_sub_moves(founds: &[Found], matched: &[bool]) -> Vec<Output> {
+    // Every window of deleted lines, by its hash.
+    let mut windows: HashMap<u64, Vec<(usize, usize)>> = HashMap::new();
+    for (index, f) in founds.iter().enumerate() {
+        if matched[index] || f.state != FoundState::Deleted { continue; }
+        for (offset, hash) in window_hashes(f).into_iter().enumerate() {
+            if !windows.contains_key(&hash) { windows.insert(hash, Vec::new()); }
+            windows.get_mut(&hash).unwrap().push((index, offset));
+        }
+    }
+
+    let mut runs: Vec<Run> = Vec::new();
+    for (added, f) in founds.iter().enumerate() {
+        if matched[added] || f.state != FoundState::Added { continue; }
+        for (new_offset, hash) in window_hashes(f).into_iter().enumerate() {
+            let candidates = match windows.get(&hash) {
+                Some(candidates) => candidates,
+                None => continue,
+            };
+            for &(deleted, old_offset) in candidates.iter() {
+                let (old, new) = (&founds[deleted].lines, &f.lines);
+                // Only start at the beginning of a run, not part way into it.
+                if old_offset > 0 && new_offset > 0 && old[old_offset - 1].key == new[new_offset - 1].key {
+                    continue;
+                }
+                let mut length = 0;
+                while old_offset + length < old.len() && new_offset + length < new.len() &&
+                      old[old_offset + length].key == new[new_offset + length].key {
+                    length += 1;
+                }
+                let significant = new[new_offset..new_offset + length].iter()
+                    .filter(|line| line.key.len() > 0)
+                    .count();
+                // Too short, or the hashes collided.
+                if significant < MIN_RUN { continue; }
+                runs.push(Run {
+                    deleted: deleted,
+                    added: added,
+                    old_offset: old_offset,
+                    new_offset: new_offset,
+                    length: length,
+                });
             }
+        }
+    }
+
+    // Longest first, ties in the order they were found.
+    runs.sort_by(|a, b| b.length.cmp(&a.length));
+    let mut used: HashSet<(usize, usize)> = HashSet::new();
+    let mut chosen: Vec<Run> = Vec::new();
+    for run in runs.into_iter() {
+        let overlaps = (0..run.length).any(|line| {
+            used.contains(&(run.deleted, run.old_offset + line)) ||
+            used.contains(&(run.added, run.new_offset + line))
+        });
+        if overlaps { continue; }
+        for line in 0..run.length {
+            used.insert((run.deleted, run.old_offset + line));
+            used.insert((run.added, run.new_offset + line));
+        }
+        chosen.push(run);
+    }
+    chosen.sort_by(|a, b| (a.added, a.new_offset).cmp(&(b.added, b.new_offset)));
 
-            moves.push(output);
+    chosen.iter().map(|run| {
+        let (deleted, added) = (&founds[run.deleted], &founds[run.added]);
+        let old_lines = &deleted.lines[run.old_offset..run.old_offset + run.length];
+        let new_lines = &added.lines[run.new_offset..run.new_offset + run.length];
+        Output {
+            old_filename: deleted.filename.clone(),
+            new_filename: added.filename.clone(),
+            origin_line: deleted.start_position + run.old_offset as u32,
+            destination_line: added.start_position + run.new_offset as u32,
+            num_lines: run.length as u32,
+            similarity: 1.0,
+            renames: find_renames(&run_names(old_lines), &run_names(new_lines)),
+        }
+    }).collect()
+}
+
+fn blob_text(repo: &Repository, id: Oid) -> String {
+    match repo.find_blob(id) {
+        Ok(blob) => String::from_utf8_lossy(blob.content()).into_owned(),
+        Err(_) => String::new(),
+    }
+}
+
+fn find_file_renames(repo: &Repository, diff: &Diff) -> Result<Vec<FileRename>, git2::Error> {
+    let mut renames = Vec::new();
+    for delta in diff.deltas() {
+        let kind = match delta.status() {
+            Delta::Renamed => "rename",
+            Delta::Copied => "copy",
+            _ => continue,
+        };
+        let (old_file, new_file) = (delta.old_file(), delta.new_file());
+        let (old_filename, new_filename) = match (old_file.path().and_then(|path| path.to_str()),
+                                                  new_file.path().and_then(|path| path.to_str())) {
+            (Some(old_filename), Some(new_filename)) => (old_filename, new_filename),
+            _ => continue,
+        };
+        let score = if old_file.id() == new_file.id() {
+            1.0
         } else {
-            map.insert(f.key.clone(), f.clone());
+            similarity::line_similarity(&blob_text(repo, old_file.id()), &blob_text(repo, new_file.id()))
+        };
+        renames.push(FileRename {
+            kind: String::from_str(kind),
+            old_filename: String::from_str(old_filename),
+            new_filename: String::from_str(new_filename),
+            similarity: (score * 100.0).round() as u32,
+        });
+    }
+    Ok(renames)
+}
+
+// Adds the generated and non-diffable files in `diff` to the excludes of `paths`.
+fn skip_generated(repo: &Repository, old_tree: &Tree, new_tree: &Tree, diff: &Diff,
+                  paths: &Pathspec) -> Pathspec {
+    let mut paths = paths.clone();
+    let mut old_attributes = Attributes::new(repo, old_tree);
+    let mut new_attributes = Attributes::new(repo, new_tree);
+    for delta in diff.deltas() {
+        if let Some(path) = delta.old_file().path().and_then(|path| path.to_str()) {
+            if old_attributes.skips(path) { paths.exclude.push(String::from_str(path)); }
+        }
+        if let Some(path) = delta.new_file().path().and_then(|path| path.to_str()) {
+            if new_attributes.skips(path) { paths.exclude.push(String::from_str(path)); }
+        }
+    }
+    paths
+}
+
+// The normalized lines of a file in the old tree.
+struct Source {
+    filename: String,
+    lines: Vec<Line>,
+    // The hash of every `MIN_RUN` lines in a row.
+    windows: Vec<u64>,
+}
+
+// The files of a tree, and where its trees are so they can be kept cached.
+struct TreeIndex {
+    sources: Vec<Rc<Source>>,
+    subtrees: Vec<(Oid, String)>,
+}
+
+type TreeCache = HashMap<(Oid, String), Rc<TreeIndex>>;
+
+// Keeps a cached tree, and every tree inside of it, in `used`.
+fn keep_tree(cache: &TreeCache, key: &(Oid, String), used: &mut TreeCache) {
+    if let Some(index) = cache.get(key) {
+        for subtree in index.subtrees.iter() {
+            keep_tree(cache, subtree, used);
+        }
+        used.insert(key.clone(), index.clone());
+    }
+}
+
+// Added hunks which weren't part of a move, and are the same as code in the
+// old tree that wasn't deleted.
+fn find_copies(analyser: &Analyser, old_tree: &Tree, founds: &[Found], matched: &[bool],
+               paths: &Pathspec) -> Result<Vec<CopyOutput>, git2::Error> {
+    let candidates: Vec<&Found> = founds.iter().zip(matched.iter())
+        .filter(|&(f, matched)| !*matched && f.state == FoundState::Added)
+        .map(|(f, _)| f)
+        .filter(|f| f.lines.iter().filter(|line| line.key.len() > 0).count() >= MIN_RUN)
+        .collect();
+    // Indexing the whole tree is slow, so don't unless there is a point.
+    if candidates.is_empty() { return Ok(Vec::new()); }
+
+    let index = try!(analyser.index(old_tree));
+    // Generated files are only known to `paths`.
+    let sources: Vec<&Source> = index.sources.iter()
+        .map(|source| &**source)
+        .filter(|source| paths.matches(&source.filename))
+        .collect();
+    let mut windows: HashMap<u64, Vec<(usize, usize)>> = HashMap::new();
+    for (index, source) in sources.iter().enumerate() {
+        for (offset, &hash) in source.windows.iter().enumerate() {
+            if !windows.contains_key(&hash) { windows.insert(hash, Vec::new()); }
+            windows.get_mut(&hash).unwrap().push((index, offset));
+        }
+    }
+
+    // Deleted code was moved, not copied.
+    let mut deleted: HashSet<(&str, u32)> = HashSet::new();
+    for f in founds.iter().filter(|f| f.state == FoundState::Deleted) {
+        for line in f.start_position..f.start_position + f.line_count {
+            deleted.insert((&f.filename[..], line));
+        }
+    }
+
+    let mut copies = Vec::new();
+    for added in candidates.iter() {
+        let first = match window_hashes(added).first() {
+            Some(&first) => first,
+            None => continue,
+        };
+        let locations = match windows.get(&first) {
+            Some(locations) => locations,
+            None => continue,
+        };
+        let length = added.lines.len();
+        let found = locations.iter().find(|&&(index, offset)| {
+            let source = &sources[index];
+            offset + length <= source.lines.len() &&
+                source.lines[offset..offset + length].iter().zip(added.lines.iter())
+                    .all(|(old, new)| old.key == new.key) &&
+                (0..length).all(|line| !deleted.contains(&(&source.filename[..], (offset + line) as u32 + 1)))
+        });
+        if let Some(&(index, offset)) = found {
+            copies.push(CopyOutput {
+                source_filename: sources[index].filename.clone(),
+                new_filename: added.filename.clone(),
+                source_line: offset as u32 + 1,
+                destination_line: added.start_position,
+                num_lines: length as u32,
+            });
+        }
+    }
+    Ok(copies)
+}
+
+// An item on one side of a diff, which doesn't exist on the other side of it.
+struct ChangedItem {
+    filename: String,
+    item: Item,
+    key: String,
+    // Index of the enclosing item in the same list.
+    parent: Option<usize>,
+}
+
+fn parse_blob(repo: &Repository, id: Oid) -> Vec<Item> {
+    // Added and deleted files have a zeroed id on one side.
+    match repo.find_blob(id) {
+        Ok(blob) => match str::from_utf8(blob.content()) {
+            Ok(source) => items::parse_items(source),
+            Err(_) => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+// `src/b/parse.rs` is the module `b::parse`, and `src/b/mod.rs` is `b`.
+fn module_path(filename: &str) -> Vec<String> {
+    let mut segments: Vec<&str> = filename.split('/').collect();
+    if segments.first() == Some(&"src") { segments.remove(0); }
+    if let Some(last) = segments.pop() {
+        let module = last.trim_right_matches(".rs");
+        if module != "mod" && module != "lib" && module != "main" {
+            segments.push(module);
+        }
+    }
+    segments.iter().map(|segment| String::from_str(segment)).collect()
+}
+
+fn item_path(changed: &ChangedItem) -> String {
+    let mut segments = module_path(&changed.filename);
+    segments.push(changed.item.path());
+    segments.connect("::")
+}
+
+// Adds the items whose key isn't in `other_keys` to `changed`.
+fn collect_changed(filename: &str, items: &[Item], keys: &[String], other_keys: &[String],
+                   changed: &mut Vec<ChangedItem>) {
+    // Items are numbered per file, but parents are numbered per list.
+    let mut indices: HashMap<usize, usize> = HashMap::new();
+    for (index, (item, key)) in items.iter().zip(keys.iter()).enumerate() {
+        if other_keys.contains(key) { continue; }
+        indices.insert(index, changed.len());
+        changed.push(ChangedItem {
+            filename: String::from_str(filename),
+            item: item.clone(),
+            key: key.clone(),
+            parent: item.parent.and_then(|parent| indices.get(&parent).cloned()),
+        });
+    }
+}
+
+// Rust items which were moved. Both sides of every changed `.rs` file are
+// parsed into items, and items which only exist on one side are compared.
+fn find_item_moves(repo: &Repository, diff: &Diff, registry: &Registry,
+                   paths: &Pathspec) -> Result<Vec<ItemMove>, git2::Error> {
+    let mut deleted: Vec<ChangedItem> = Vec::new();
+    let mut added: Vec<ChangedItem> = Vec::new();
+
+    for delta in diff.deltas() {
+        let (old_file, new_file) = (delta.old_file(), delta.new_file());
+        let (old_path, new_path) = match (old_file.path(), new_file.path()) {
+            (Some(old_path), Some(new_path)) => (old_path, new_path),
+            _ => continue,
+        };
+        if !has_extension(old_path, &["rs"]) && !has_extension(new_path, &["rs"]) { continue; }
+        let (old_filename, new_filename) = match (old_path.to_str(), new_path.to_str()) {
+            (Some(old_filename), Some(new_filename)) => (old_filename, new_filename),
+            _ => continue,
+        };
+        if !paths.matches(old_filename) && !paths.matches(new_filename) { continue; }
+        let normalizer = registry.find(old_path, new_path);
+        let old_items = parse_blob(repo, old_file.id());
+        let new_items = parse_blob(repo, new_file.id());
+        let old_keys: Vec<String> = old_items.iter().map(|item| normalizer.normalize(item.source.clone())).collect();
+        let new_keys: Vec<String> = new_items.iter().map(|item| normalizer.normalize(item.source.clone())).collect();
+
+        collect_changed(old_filename, &old_items, &old_keys, &new_keys, &mut deleted);
+        collect_changed(new_filename, &new_items, &new_keys, &old_keys, &mut added);
+    }
+
+    // Pair deleted items with added items of the same kind and key.
+    let mut pairs: Vec<Option<usize>> = deleted.iter().map(|_| None).collect();
+    let mut taken: Vec<bool> = added.iter().map(|_| false).collect();
+    for (deleted_index, old) in deleted.iter().enumerate() {
+        let found = added.iter().zip(taken.iter()).position(|(new, taken)| {
+            !*taken && new.key == old.key && new.item.kind == old.item.kind
+        });
+        if let Some(added_index) = found {
+            taken[added_index] = true;
+            pairs[deleted_index] = Some(added_index);
         }
     }
 
-    return Ok(moves);
+    let mut moves = Vec::new();
+    for (deleted_index, pair) in pairs.iter().enumerate() {
+        let added_index = match *pair {
+            Some(added_index) => added_index,
+            None => continue,
+        };
+        let (old, new) = (&deleted[deleted_index], &added[added_index]);
+        // Items inside of a moved item, like the methods of an `impl`, moved with it.
+        if old.parent.map_or(false, |parent| pairs[parent].is_some()) { continue; }
+        moves.push(ItemMove {
+            kind: old.item.kind.clone(),
+            old_path: item_path(old),
+            new_path: item_path(new),
+            old_filename: old.filename.clone(),
+            new_filename: new.filename.clone(),
+            origin_line: old.item.line,
+            destination_line: new.item.line,
+            num_lines: new.item.line_count,
+        });
+    }
+
+    Ok(moves)
 }
 
-#[derive(Debug, RustcEncodable)]
+#[derive(Debug, RustcEncodable, RustcDecodable)]
 pub struct OutputSet {
+    diff: DiffSettings,
     old: TransitOid,
+    // Which parent of `new` that `old` is. `None` if it isn't a parent, or if
+    // this is the combined result of comparing a merge against all of its parents.
+    parent: Option<usize>,
     old_time: i64, // Seconds from Epoch
     new: TransitOid,
     new_time: i64, // Seconds from Epoch
     outputs: Vec<Output>,
+    leftovers: Vec<Leftover>,
+    copies: Vec<CopyOutput>,
+    item_moves: Vec<ItemMove>,
+    // Moves of code deleted by an earlier commit, only found when walking a repo.
+    window_moves: Vec<WindowMove>,
+    file_renames: Vec<FileRename>,
     added_lines: u32,
+    // See `Window` for code which a later commit moves back in.
     deleted_lines: u32,
 }
 
-#[derive(Debug, RustcEncodable)]
+impl OutputSet {
+    /// The settings the commits were diffed with.
+    pub fn diff(&self) -> &DiffSettings { &self.diff }
+    pub fn old(&self) -> Oid { self.old.0 }
+    /// Which parent of `new` that `old` is, if it is one.
+    pub fn parent(&self) -> Option<usize> { self.parent }
+    /// Seconds from Epoch.
+    pub fn old_time(&self) -> i64 { self.old_time }
+    pub fn new(&self) -> Oid { self.new.0 }
+    /// Seconds from Epoch.
+    pub fn new_time(&self) -> i64 { self.new_time }
+    pub fn outputs(&self) -> &[Output] { &self.outputs }
+    pub fn leftovers(&self) -> &[Leftover] { &self.leftovers }
+    pub fn copies(&self) -> &[CopyOutput] { &self.copies }
+    pub fn item_moves(&self) -> &[ItemMove] { &self.item_moves }
+    pub fn window_moves(&self) -> &[WindowMove] { &self.window_moves }
+    pub fn file_renames(&self) -> &[FileRename] { &self.file_renames }
+    /// Added lines which weren't part of a move, including those in `window_moves`.
+    pub fn added_lines(&self) -> u32 { self.added_lines }
+    /// Deleted lines which weren't part of a move in this pair of commits,
+    /// see `Window`.
+    pub fn deleted_lines(&self) -> u32 { self.deleted_lines }
+}
+
+#[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
 pub struct Output {
     old_filename: String,
     new_filename: String,
     origin_line: u32,
     destination_line: u32,
     num_lines: u32,
+    // `1.0` unless the hunks were matched by similarity.
+    similarity: f64,
+    // Empty for a pure move.
+    renames: Vec<Rename>,
+}
+
+impl Output {
+    pub fn old_filename(&self) -> &str { &self.old_filename }
+    pub fn new_filename(&self) -> &str { &self.new_filename }
+    pub fn origin_line(&self) -> u32 { self.origin_line }
+    pub fn destination_line(&self) -> u32 { self.destination_line }
+    pub fn num_lines(&self) -> u32 { self.num_lines }
+    /// From 0 to 1, `1.0` unless the hunks were matched by similarity.
+    pub fn similarity(&self) -> f64 { self.similarity }
+    /// The identifiers renamed in the move, empty for a pure one.
+    pub fn renames(&self) -> &[Rename] { &self.renames }
+}
+
+/// A file which git found was renamed or copied. Moves inside of it are found
+/// by comparing it against `old_filename`.
+#[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct FileRename {
+    // `rename` or `copy`.
+    kind: String,
+    old_filename: String,
+    new_filename: String,
+    // In percent.
+    similarity: u32,
+}
+
+impl FileRename {
+    /// `rename` or `copy`.
+    pub fn kind(&self) -> &str { &self.kind }
+    pub fn old_filename(&self) -> &str { &self.old_filename }
+    pub fn new_filename(&self) -> &str { &self.new_filename }
+    /// In percent.
+    pub fn similarity(&self) -> u32 { self.similarity }
+}
+
+/// A move which spans commits, the code was deleted in `old_commit` and added
+/// back in `new_commit`.
+#[derive(Debug, RustcEncodable, RustcDecodable)]
+pub struct WindowMove {
+    old_commit: TransitOid,
+    new_commit: TransitOid,
+    output: Output,
+}
+
+impl WindowMove {
+    pub fn old_commit(&self) -> Oid { self.old_commit.0 }
+    pub fn new_commit(&self) -> Oid { self.new_commit.0 }
+    pub fn output(&self) -> &Output { &self.output }
+}
+
+/// A hunk which is identical to one on the other side of the diff, but wasn't
+/// paired with it because the other side has fewer copies.
+#[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Leftover {
+    filename: String,
+    // `false` for a deletion.
+    added: bool,
+    line: u32,
+    num_lines: u32,
+}
+
+impl Leftover {
+    pub fn filename(&self) -> &str { &self.filename }
+    /// `false` for a deletion.
+    pub fn added(&self) -> bool { self.added }
+    pub fn line(&self) -> u32 { self.line }
+    pub fn num_lines(&self) -> u32 { self.num_lines }
+}
+
+/// Added code which is the same as code that was left in place.
+#[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct CopyOutput {
+    source_filename: String,
+    new_filename: String,
+    source_line: u32,
+    destination_line: u32,
+    num_lines: u32,
+}
+
+impl CopyOutput {
+    pub fn source_filename(&self) -> &str { &self.source_filename }
+    pub fn new_filename(&self) -> &str { &self.new_filename }
+    pub fn source_line(&self) -> u32 { self.source_line }
+    pub fn destination_line(&self) -> u32 { self.destination_line }
+    pub fn num_lines(&self) -> u32 { self.num_lines }
+}
+
+/// A Rust item, eg. `fn parse`, which moved from `old_path` to `new_path`.
+#[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct ItemMove {
+    kind: String,
+    old_path: String,
+    new_path: String,
+    old_filename: String,
+    new_filename: String,
+    origin_line: u32,
+    destination_line: u32,
+    num_lines: u32,
+}
+
+impl ItemMove {
+    /// eg. `fn` or `struct`.
+    pub fn kind(&self) -> &str { &self.kind }
+    pub fn old_path(&self) -> &str { &self.old_path }
+    pub fn new_path(&self) -> &str { &self.new_path }
+    pub fn old_filename(&self) -> &str { &self.old_filename }
+    pub fn new_filename(&self) -> &str { &self.new_filename }
+    pub fn origin_line(&self) -> u32 { self.origin_line }
+    pub fn destination_line(&self) -> u32 { self.destination_line }
+    pub fn num_lines(&self) -> u32 { self.num_lines }
+
+    // The same item moving to the same place, whichever parent it moved from.
+    fn same_item(&self, other: &ItemMove) -> bool {
+        self.kind == other.kind && self.old_path == other.old_path && self.new_path == other.new_path &&
+            self.old_filename == other.old_filename && self.new_filename == other.new_filename &&
+            self.destination_line == other.destination_line && self.num_lines == other.num_lines
+    }
+}
+
+#[derive(Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Rename {
+    from: String,
+    to: String,
 }
 
-#[derive(Debug, Hash, PartialEq, Eq)]
+impl Rename {
+    pub fn from(&self) -> &str { &self.from }
+    pub fn to(&self) -> &str { &self.to }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
 pub struct TransitOid(Oid);
 impl fmt::Display for TransitOid {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -585,3 +1764,493 @@ impl rustc_serialize::Encodable for TransitOid {
         s.emit_str(&format!("{}", self)[..])
     }
 }
+
+impl rustc_serialize::Decodable for TransitOid {
+    fn decode<D: rustc_serialize::Decoder>(d: &mut D) -> Result<TransitOid, D::Error> {
+        let string = try!(d.read_str());
+        match Oid::from_str(&string) {
+            Ok(oid) => Ok(TransitOid(oid)),
+            Err(_) => Err(d.error("Invalid object id.")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Diff, Oid, Repository};
+    use rustc_serialize::json;
+
+    use std::path::Path;
+    use std::str::FromStr;
+
+    use super::{commits, Algorithm, Found, FoundState, Options, OutputSet, Parents, RepoWalk, Whitespace, Window};
+    use super::{find_additions_and_deletions, pair_identical, path_distance};
+    use fixture;
+    use normalizer::{KeyNormalizer, Plain, Registry};
+    use pathspec::Pathspec;
+    use normalizer::rust::Rust;
+    use revisions::Revisions;
+
+    fn compare(repo: &Repository, old: Oid, new: Oid, options: &Options) -> OutputSet {
+        let revisions = Revisions::pair(repo, &old.to_string(), &new.to_string()).unwrap();
+        commits(fixture::reopen(repo), &revisions, options).unwrap()
+    }
+
+    const NUMBERS: &'static [&'static str] = &[
+        "fn one() -> u32 { 1 }", "fn two() -> u32 { 2 }", "fn three() -> u32 { 3 }",
+        "fn four() -> u32 { 4 }", "fn five() -> u32 { 5 }", "fn six() -> u32 { 6 }",
+        "fn seven() -> u32 { 7 }", "fn eight() -> u32 { 8 }", "fn nine() -> u32 { 9 }",
+        "fn ten() -> u32 { 10 }",
+    ];
+
+    fn lines(lines: &[&str]) -> String {
+        format!("{}\n", lines.connect("\n"))
+    }
+
+    // A history where one function moves from the top of a file to the
+    // bottom in every commit.
+    fn history(name: &str) -> Repository {
+        let repo = fixture::repo(name);
+        for step in 0..NUMBERS.len() {
+            let mut rotated = NUMBERS[step..].to_vec();
+            rotated.push_all(&NUMBERS[..step]);
+            fixture::commit(&repo, &[("a.rs", &lines(&rotated))], step as i64 * 60);
+        }
+        repo
+    }
+
+    fn walk(repo: &Repository, jobs: usize) -> Vec<String> {
+        let mut options = Options::default();
+        options.jobs = jobs;
+        let revisions = Revisions::resolve(repo, &[]).unwrap();
+        RepoWalk::new(repo, &revisions, &options).unwrap()
+            .map(|set| json::encode(&set.unwrap()).unwrap())
+            .collect()
+    }
+
+    fn found(filename: &str, state: FoundState, line: u32) -> Found {
+        Found::new(&Plain, String::from_str(filename), "let a = 1;\nlet b = 2;\n", state, line, 2)
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(path_distance("src/a.rs", "src/b.rs"), 0);
+        assert_eq!(path_distance("src/a/x.rs", "src/b/y.rs"), 2);
+        assert_eq!(path_distance("x.rs", "src/a/y.rs"), 2);
+    }
+
+    #[test]
+    fn duplicates_are_paired() {
+        let founds = vec![
+            found("src/a/x.rs", FoundState::Deleted, 10),
+            found("src/b/y.rs", FoundState::Deleted, 20),
+            found("src/b/z.rs", FoundState::Added, 5),
+            found("src/a/x.rs", FoundState::Added, 40),
+        ];
+        let mut matched = vec![false; 4];
+        // Same file first, then the nearest directory.
+        assert_eq!(pair_identical(&founds, &mut matched), vec![(0, 3), (1, 2)]);
+        assert!(matched.iter().all(|m| *m));
+    }
+
+    #[test]
+    fn copies_of_files_delete_nothing() {
+        let repo = fixture::repo("copies_of_files_delete_nothing");
+        let source = lines(NUMBERS);
+        let old = fixture::commit(&repo, &[("a.rs", &source)], 0);
+        // The source is changed too, or git doesn't look for copies of it.
+        let changed = format!("{}fn eleven() -> u32 {{ 11 }}\n", source);
+        let copy = format!("fn zero() -> u32 {{ 0 }}\n{}", lines(&NUMBERS[1..]));
+        let new = fixture::commit(&repo, &[("a.rs", &changed), ("b.rs", &copy)], 60);
+        let set = compare(&repo, old, new, &Options::default());
+        assert!(set.file_renames.iter().any(|rename| rename.kind == "copy"));
+        assert_eq!(set.deleted_lines, 0);
+        assert_eq!(set.added_lines, 2);
+        assert!(set.outputs.is_empty());
+    }
+
+    #[test]
+    fn fingerprints() {
+        let fingerprint = Options::default().fingerprint();
+        assert_eq!(Options::default().fingerprint(), fingerprint);
+        for change in 0..6 {
+            let mut options = Options::default();
+            match change {
+                0 => options.threshold = 0.8,
+                1 => options.diff.context_lines = 5,
+                2 => options.diff.algorithm = Algorithm::Patience,
+                3 => options.paths.include.push(String::from_str("src")),
+                4 => options.paths.exclude.push(String::from_str("Cargo.lock")),
+                _ => options.registry.register(Box::new(Rust::deep())),
+            }
+            assert!(options.fingerprint() != fingerprint);
+        }
+        // Neither changes what is found between two commits.
+        let mut options = Options::default();
+        options.jobs = 3;
+        options.cache = None;
+        assert_eq!(options.fingerprint(), fingerprint);
+    }
+
+    // Two branches off of `a.rs`, one adding `c.rs` and the other `e.rs`,
+    // merged with `files` on top of both. Returns the merge and its parents.
+    fn merged(name: &str, files: &[(&str, &str)]) -> (Repository, Oid, Oid, Oid) {
+        let repo = fixture::repo(name);
+        let root = fixture::commit(&repo, &[("a.rs", &lines(NUMBERS))], 0);
+        let main_side = "fn main_side() -> u32 { 0 }\n";
+        let other_side = "fn other_side() -> u32 { 0 }\nfn more() -> u32 { 0 }\n";
+        let main = fixture::commit_onto(&repo, &[root], &[("a.rs", &lines(NUMBERS)), ("c.rs", main_side)], 60);
+        let side = fixture::commit_onto(&repo, &[root], &[("a.rs", &lines(NUMBERS)), ("e.rs", other_side)], 120);
+        let mut tree = vec![("c.rs", main_side), ("e.rs", other_side)];
+        tree.push_all(files);
+        let merge = fixture::commit_onto(&repo, &[main, side], &tree, 180);
+        (repo, merge, main, side)
+    }
+
+    fn merge_sets(repo: &Repository, merge: Oid, main: Oid, side: Oid, parents: Parents) -> Vec<OutputSet> {
+        let specs = vec![merge.to_string(), format!("^{}", main), format!("^{}", side)];
+        let revisions = Revisions::resolve(repo, &specs).unwrap();
+        let mut options = Options::default();
+        options.parents = parents;
+        super::repo(fixture::reopen(repo), &revisions, &options).unwrap()
+    }
+
+    #[test]
+    fn every_parent() {
+        let a = lines(NUMBERS);
+        let (repo, merge, main, side) = merged("every_parent", &[("a.rs", &a)]);
+        let sets = merge_sets(&repo, merge, main, side, Parents::Every);
+        assert_eq!(sets.len(), 2);
+        // Each parent is missing what the other branch added.
+        assert_eq!((sets[0].parent, sets[0].added_lines), (Some(0), 2));
+        assert_eq!((sets[1].parent, sets[1].added_lines), (Some(1), 1));
+    }
+
+    #[test]
+    fn clean_merges_change_nothing() {
+        let a = lines(NUMBERS);
+        let (repo, merge, main, side) = merged("clean_merges_change_nothing", &[("a.rs", &a)]);
+        let sets = merge_sets(&repo, merge, main, side, Parents::Combined);
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].parent, None);
+        assert_eq!((sets[0].added_lines, sets[0].deleted_lines), (0, 0));
+        assert!(sets[0].outputs.is_empty());
+    }
+
+    #[test]
+    fn evil_merges_keep_their_own_changes() {
+        let mut rest = NUMBERS[..2].to_vec();
+        rest.push_all(&NUMBERS[6..]);
+        let (a, d) = (lines(&rest), lines(&NUMBERS[2..6]));
+        let f = "fn evil() -> u32 { 666 }\n";
+        let (repo, merge, main, side) = merged("evil_merges_keep_their_own_changes",
+                                               &[("a.rs", &a), ("d.rs", &d), ("f.rs", f)]);
+        let sets = merge_sets(&repo, merge, main, side, Parents::Combined);
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].outputs.len(), 1);
+        assert_eq!(&sets[0].outputs[0].old_filename[..], "a.rs");
+        assert_eq!(&sets[0].outputs[0].new_filename[..], "d.rs");
+        assert_eq!(sets[0].outputs[0].num_lines, 4);
+        assert_eq!((sets[0].added_lines, sets[0].deleted_lines), (1, 0));
+    }
+
+    #[test]
+    fn walks_match_repo() {
+        let repo = history("walks_match_repo");
+        let revisions = Revisions::resolve(&repo, &[]).unwrap();
+        let sets = super::repo(fixture::reopen(&repo), &revisions, &Options::default()).unwrap();
+        let sets: Vec<String> = sets.iter().map(|set| json::encode(set).unwrap()).collect();
+        // The root commit has nothing to be compared against.
+        assert_eq!(sets.len(), NUMBERS.len() - 1);
+        assert_eq!(walk(&repo, 1), sets);
+    }
+
+    #[test]
+    fn walks_stop_early() {
+        let repo = history("walks_stop_early");
+        let revisions = Revisions::resolve(&repo, &[]).unwrap();
+        let mut options = Options::default();
+        options.jobs = 4;
+        let first: Vec<String> = RepoWalk::new(&repo, &revisions, &options).unwrap()
+            .take(2)
+            .map(|set| json::encode(&set.unwrap()).unwrap())
+            .collect();
+        assert_eq!(&first[..], &walk(&repo, 1)[..2]);
+    }
+
+    // The library example in the README, through the crate's re-exports.
+    #[test]
+    fn readme_example() {
+        let repo = history("readme_example");
+        let revisions = ::Revisions::resolve(&repo, &[]).unwrap();
+        let options = ::Options::default();
+        let mut walked = 0;
+        for set in ::RepoWalk::new(&repo, &revisions, &options).unwrap().take(10) {
+            let set = set.unwrap();
+            assert!(set.new() != set.old());
+            assert_eq!(set.outputs().len(), 1);
+            walked += 1;
+        }
+        assert_eq!(walked, NUMBERS.len() - 1);
+    }
+
+    #[test]
+    fn parallel_walks_match_sequential_ones() {
+        let repo = history("parallel_walks_match_sequential_ones");
+        let sequential = walk(&repo, 1);
+        assert_eq!(sequential.len(), NUMBERS.len() - 1);
+        assert_eq!(walk(&repo, 4), sequential);
+    }
+
+    #[test]
+    fn replaced_lines_are_counted() {
+        let repo = fixture::repo("replaced_lines_are_counted");
+        let old = fixture::commit(&repo, &[("a.rs", &lines(NUMBERS))], 0);
+        let mut replaced = NUMBERS.to_vec();
+        replaced[4] = "fn fifty() -> u32 { 50 }";
+        let new = fixture::commit(&repo, &[("a.rs", &lines(&replaced))], 60);
+        // A `-` line directly followed by a `+` line, each is a hunk of one line.
+        let set = compare(&repo, old, new, &Options::default());
+        assert_eq!(set.deleted_lines, 1);
+        assert_eq!(set.added_lines, 1);
+    }
+
+    #[test]
+    fn diff_settings_from_str() {
+        // Each mode is spelled the way it prints, in lower case.
+        for algorithm in [Algorithm::Myers, Algorithm::Minimal, Algorithm::Patience].iter() {
+            let name = format!("{:?}", algorithm).to_lowercase();
+            assert_eq!(Algorithm::from_str(&name), Ok(*algorithm));
+        }
+        for whitespace in [Whitespace::Keep, Whitespace::All, Whitespace::Change, Whitespace::Eol].iter() {
+            let name = format!("{:?}", whitespace).to_lowercase();
+            assert_eq!(Whitespace::from_str(&name), Ok(*whitespace));
+        }
+        assert!(Algorithm::from_str("histogram").is_err());
+        assert!(Whitespace::from_str("none").is_err());
+    }
+
+    #[test]
+    fn blank_lines_are_ignored() {
+        let repo = fixture::repo("blank_lines_are_ignored");
+        let old = fixture::commit(&repo, &[("a.rs", &lines(NUMBERS))], 0);
+        let mut changed = NUMBERS[..5].to_vec();
+        changed.push_all(&["", "    "]);
+        changed.push_all(&NUMBERS[5..]);
+        changed.push("fn hundred() -> u32 { 100 }");
+        let new = fixture::commit(&repo, &[("a.rs", &lines(&changed))], 60);
+        assert_eq!(compare(&repo, old, new, &Options::default()).added_lines, 3);
+        let mut options = Options::default();
+        options.diff.ignore_blank_lines = true;
+        assert_eq!(compare(&repo, old, new, &options).added_lines, 1);
+    }
+
+    #[test]
+    fn deletions_are_not_counted_as_additions() {
+        let repo = fixture::repo("deletions_are_not_counted_as_additions");
+        let old = fixture::commit(&repo, &[("a.rs", &lines(NUMBERS))], 0);
+        let mut changed: Vec<&str> = NUMBERS.iter().enumerate()
+            .filter(|&(index, _)| index != 2 && index != 3)
+            .map(|(_, line)| *line)
+            .collect();
+        changed.push("fn hundred() -> u32 { 100 }");
+        let new = fixture::commit(&repo, &[("a.rs", &lines(&changed))], 60);
+        let set = compare(&repo, old, new, &Options::default());
+        assert_eq!(set.deleted_lines, 2);
+        assert_eq!(set.added_lines, 1);
+    }
+
+    #[test]
+    fn hunks_end_with_their_file() {
+        let repo = fixture::repo("hunks_end_with_their_file");
+        let old = fixture::commit(&repo, &[("a.rs", &lines(&NUMBERS[..5])), ("b.rs", &lines(&NUMBERS[5..]))], 0);
+        // `a.rs` ends with an addition, and `b.rs` starts with one.
+        let mut a = NUMBERS[..5].to_vec();
+        a.push("fn eleven() -> u32 { 11 }");
+        let mut b = vec!["fn twelve() -> u32 { 12 }"];
+        b.push_all(&NUMBERS[5..]);
+        let new = fixture::commit(&repo, &[("a.rs", &lines(&a)), ("b.rs", &lines(&b))], 60);
+        let (old, new) = (repo.find_commit(old).unwrap(), repo.find_commit(new).unwrap());
+        let diff = Diff::tree_to_tree(&repo, Some(&old.tree().unwrap()), Some(&new.tree().unwrap()), None).unwrap();
+        let founds = find_additions_and_deletions(diff, &Registry::new(), &Pathspec::default());
+        let hunks: Vec<(&str, u32, u32)> = founds.iter()
+            .map(|found| (&found.filename[..], found.start_position, found.line_count))
+            .collect();
+        assert_eq!(hunks, vec![("a.rs", 6, 1), ("b.rs", 1, 1)]);
+    }
+
+    // Ignores numbers, so code whose numbers changed still matches.
+    struct Digitless;
+
+    impl KeyNormalizer for Digitless {
+        fn name(&self) -> &str { "digitless" }
+        fn handles(&self, _: &Path) -> bool { true }
+        fn normalize(&self, hunk: String) -> String {
+            hunk.chars().filter(|c| !c.is_digit(10) && !c.is_whitespace()).collect()
+        }
+    }
+
+    #[test]
+    fn later_normalizers_win() {
+        let repo = fixture::repo("later_normalizers_win");
+        let old = fixture::commit(&repo, &[("a.rs", &lines(NUMBERS))], 0);
+        // `fn five` moves to the end, and returns another number.
+        let mut moved = NUMBERS.to_vec();
+        moved.remove(4);
+        moved.push("fn five() -> u32 { 55 }");
+        let new = fixture::commit(&repo, &[("a.rs", &lines(&moved))], 60);
+        assert!(compare(&repo, old, new, &Options::default()).outputs.is_empty());
+        let mut options = Options::default();
+        options.registry.register(Box::new(Digitless));
+        assert_eq!(compare(&repo, old, new, &options).outputs.len(), 1);
+
+        // Across languages `Plain` is used, even though `Digitless` handles both.
+        let renamed = fixture::commit(&repo, &[("a.py", &lines(&moved))], 120);
+        let set = compare(&repo, old, renamed, &options);
+        assert_eq!(set.file_renames.len(), 1);
+        assert!(set.outputs.is_empty());
+    }
+
+    // `fn five` is deleted from one file, and added to another two commits
+    // and `days` days later.
+    fn moved_later(name: &str, days: i64) -> Repository {
+        let repo = fixture::repo(name);
+        let mut without = NUMBERS.to_vec();
+        without.remove(4);
+        let (with, without) = (lines(NUMBERS), lines(&without));
+        fixture::commit(&repo, &[("a.rs", &with), ("b.rs", "fn b() {}\n")], 0);
+        fixture::commit(&repo, &[("a.rs", &without), ("b.rs", "fn b() {}\n")], 60);
+        fixture::commit(&repo, &[("a.rs", &without), ("b.rs", "fn b() { 2 }\n")], 120);
+        let added = format!("fn b() {{ 2 }}\n{}\n", NUMBERS[4]);
+        fixture::commit(&repo, &[("a.rs", &without), ("b.rs", &added)], days * 24 * 60 * 60 + 180);
+        repo
+    }
+
+    // The window moves of every set of a walk, and the lines added by the last.
+    fn window_moves(repo: &Repository, window: Window) -> (usize, u32) {
+        let mut options = Options::default();
+        options.window = Some(window);
+        let revisions = Revisions::resolve(repo, &[]).unwrap();
+        let sets: Vec<OutputSet> = RepoWalk::new(repo, &revisions, &options).unwrap()
+            .map(|set| set.unwrap())
+            .collect();
+        let moves = sets.iter().fold(0, |moves, set| moves + set.window_moves.len());
+        (moves, sets.last().unwrap().added_lines)
+    }
+
+    #[test]
+    fn moves_within_windows() {
+        let repo = moved_later("moves_within_windows", 0);
+        assert_eq!(window_moves(&repo, Window::Commits(2)), (1, 0));
+        assert_eq!(window_moves(&repo, Window::Commits(1)), (0, 1));
+        let repo = moved_later("moves_within_windows_days", 2);
+        assert_eq!(window_moves(&repo, Window::Days(3)), (1, 0));
+        assert_eq!(window_moves(&repo, Window::Days(1)), (0, 1));
+    }
+
+    // `count` different functions, so no more than 4 of them are ever a
+    // large enough part of a file for git to call it a copy.
+    fn numbered(count: u32) -> Vec<String> {
+        (0..count).map(|n| format!("fn f{}() -> u32 {{ {} }}", n, n)).collect()
+    }
+
+    fn text(lines: &[String]) -> String {
+        lines.iter().map(|line| format!("{}\n", line)).collect::<Vec<String>>().concat()
+    }
+
+    #[test]
+    fn copies_of_unchanged_code() {
+        let repo = fixture::repo("copies_of_unchanged_code");
+        let functions = numbered(20);
+        let old = fixture::commit(&repo, &[("a.rs", &text(&functions))], 0);
+        let new = fixture::commit(&repo, &[("a.rs", &text(&functions)), ("b.rs", &text(&functions[2..6]))], 60);
+        let mut options = Options::default();
+        options.copies = true;
+        let set = compare(&repo, old, new, &options);
+        assert_eq!(set.copies.len(), 1);
+        assert_eq!(&set.copies[0].source_filename[..], "a.rs");
+        assert_eq!(set.copies[0].source_line, 3);
+        assert_eq!(set.copies[0].destination_line, 1);
+        assert_eq!(set.copies[0].num_lines, 4);
+        // Without it, nothing is looked for.
+        assert!(compare(&repo, old, new, &Options::default()).copies.is_empty());
+    }
+
+    #[test]
+    fn moves_are_not_copies() {
+        let repo = fixture::repo("moves_are_not_copies");
+        let functions = numbered(20);
+        let mut rest = functions[..2].to_vec();
+        rest.push_all(&functions[6..]);
+        // `c.rs` is the same as the code which moves, and stays.
+        let old = fixture::commit(&repo, &[("a.rs", &text(&functions)), ("c.rs", &text(&functions[2..6]))], 0);
+        let new = fixture::commit(&repo, &[("a.rs", &text(&rest)), ("b.rs", &text(&functions[2..6])),
+                                           ("c.rs", &text(&functions[2..6]))], 60);
+        let mut options = Options::default();
+        options.copies = true;
+        let set = compare(&repo, old, new, &options);
+        assert_eq!(set.outputs.len(), 1);
+        assert!(set.copies.is_empty());
+    }
+
+    #[test]
+    fn items_moved_between_modules() {
+        let repo = fixture::repo("items_moved_between_modules");
+        let impl_s = "struct S;\n\nimpl S {\n    fn new() -> S { S }\n";
+        let old = fixture::commit(&repo, &[("src/a.rs", "fn keep() {}\nfn helper(x: u32) -> u32 {\n    x + 1\n}\n"),
+                                           ("src/b.rs", &format!("{}}}\n", impl_s))], 0);
+        // `helper` moves into `impl S`, and its parameter is renamed on the way.
+        let moved = format!("{}\n    fn helper(y: u32) -> u32 {{\n        y + 1\n    }}\n}}\n", impl_s);
+        let new = fixture::commit(&repo, &[("src/a.rs", "fn keep() {}\n"), ("src/b.rs", &moved)], 60);
+        let set = compare(&repo, old, new, &Options::default());
+        assert_eq!(set.item_moves.len(), 1);
+        let item_move = &set.item_moves[0];
+        assert_eq!(item_move.kind(), "fn");
+        assert_eq!(item_move.old_path(), "a::helper");
+        assert_eq!(item_move.new_path(), "b::S::helper");
+        assert_eq!(item_move.old_filename(), "src/a.rs");
+        assert_eq!(item_move.new_filename(), "src/b.rs");
+        assert_eq!((item_move.origin_line, item_move.destination_line, item_move.num_lines), (2, 6, 3));
+    }
+
+    #[test]
+    fn deep_moves_report_renames() {
+        let repo = fixture::repo("deep_moves_report_renames");
+        let first = "fn first<T: Clone>(items: &[T]) -> T {\n    items[0].clone()\n}\n";
+        let renamed = "fn first<Item: Clone>(list: &[Item]) -> Item {\n    list[0].clone()\n}\n";
+        let old = fixture::commit(&repo, &[("a.rs", &format!("{}{}", lines(NUMBERS), first)),
+                                           ("b.rs", "fn b() {}\n")], 0);
+        let new = fixture::commit(&repo, &[("a.rs", &lines(NUMBERS)),
+                                           ("b.rs", &format!("fn b() {{}}\n{}", renamed))], 60);
+        // The generic parameter is only renamed at the deep level.
+        assert!(compare(&repo, old, new, &Options::default()).outputs.is_empty());
+        let mut options = Options::default();
+        options.registry.register(Box::new(Rust::deep()));
+        let set = compare(&repo, old, new, &options);
+        assert_eq!(set.outputs.len(), 1);
+        let renames: Vec<(&str, &str)> = set.outputs[0].renames().iter()
+            .map(|rename| (rename.from(), rename.to()))
+            .collect();
+        assert!(renames.contains(&("T", "Item")));
+        assert!(renames.contains(&("items", "list")));
+    }
+
+    #[test]
+    fn streamed_sets_are_one_line_each() {
+        let repo = history("streamed_sets_are_one_line_each");
+        let revisions = Revisions::resolve(&repo, &[]).unwrap();
+        let mut streamed = String::new();
+        super::repo_each(fixture::reopen(&repo), &revisions, &Options::default(), |set| {
+            streamed.push_str(&json::encode(&set).unwrap());
+            streamed.push('\n');
+        }).unwrap();
+        let sets: Vec<OutputSet> = streamed.lines().map(|line| json::decode(line).unwrap()).collect();
+        assert_eq!(sets.len(), NUMBERS.len() - 1);
+        // Oldest first, each set picking up where the one before it ended.
+        for pair in sets.windows(2) {
+            assert_eq!(pair[0].new(), pair[1].old());
+        }
+        let encoded: Vec<String> = streamed.lines().map(|line| String::from_str(line)).collect();
+        assert_eq!(encoded, walk(&repo, 1));
+    }
+}
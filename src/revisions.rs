@@ -0,0 +1,199 @@
+// Resolves `git rev-parse` style input, eg. `main`, `v1.0`, `HEAD~10`, `a1b2c3`,
+// `A..B` or `A...B`, into the set of commits to analyse.
+
+use std::collections::HashSet;
+
+use git2::{self, ObjectType, Oid, Repository, Revwalk};
+
+/// The commits to analyse, all ancestors of `tips` which aren't ancestors of
+/// `hidden`, limited to those made between `since` and `until`.
+#[derive(Debug, Clone)]
+pub struct Revisions {
+    tips: Vec<Oid>,
+    hidden: Vec<Oid>,
+    /// Seconds from Epoch.
+    pub since: Option<i64>,
+    /// Seconds from Epoch.
+    pub until: Option<i64>,
+}
+
+fn resolve_one(repo: &Repository, spec: &str) -> Result<Oid, git2::Error> {
+    let object = try!(repo.revparse_single(spec));
+    // Annotated tags point to a tag object, not the commit.
+    let commit = try!(object.peel(ObjectType::Commit));
+    Ok(commit.id())
+}
+
+impl Revisions {
+    /// Resolves each of `specs`. `A..B` is everything in `B` but not in `A`,
+    /// `A...B` everything in either but not in both, and `^A` leaves out `A`.
+    /// No specs at all means `HEAD`.
+    pub fn resolve(repo: &Repository, specs: &[String]) -> Result<Revisions, git2::Error> {
+        let mut revisions = Revisions {
+            tips: Vec::new(),
+            hidden: Vec::new(),
+            since: None,
+            until: None,
+        };
+        for spec in specs.iter() {
+            if let Some(index) = spec.find("...") {
+                let (left, right) = (or_head(&spec[..index]), or_head(&spec[index + 3..]));
+                let (left, right) = (try!(resolve_one(repo, left)), try!(resolve_one(repo, right)));
+                revisions.tips.push(left);
+                revisions.tips.push(right);
+                revisions.hidden.push_all(&try!(merge_bases(repo, left, right)));
+            } else if let Some(index) = spec.find("..") {
+                revisions.hidden.push(try!(resolve_one(repo, or_head(&spec[..index]))));
+                revisions.tips.push(try!(resolve_one(repo, or_head(&spec[index + 2..]))));
+            } else if spec.starts_with("^") {
+                revisions.hidden.push(try!(resolve_one(repo, &spec[1..])));
+            } else {
+                revisions.tips.push(try!(resolve_one(repo, spec)));
+            }
+        }
+        if revisions.tips.is_empty() {
+            revisions.tips.push(try!(resolve_one(repo, "HEAD")));
+        }
+        Ok(revisions)
+    }
+
+    /// Just the two commits `old` and `new`, to be compared with each other.
+    pub fn pair(repo: &Repository, old: &str, new: &str) -> Result<Revisions, git2::Error> {
+        Ok(Revisions {
+            tips: vec![try!(resolve_one(repo, new))],
+            hidden: vec![try!(resolve_one(repo, old))],
+            since: None,
+            until: None,
+        })
+    }
+
+    /// The `(old, new)` commits to compare, if this is a single `A..B` range.
+    pub fn endpoints(&self) -> Option<(Oid, Oid)> {
+        match (&self.hidden[..], &self.tips[..]) {
+            (&[old], &[new]) => Some((old, new)),
+            _ => None,
+        }
+    }
+
+    /// Whether a commit made at `time` is inside of `since` and `until`.
+    pub fn includes_time(&self, time: i64) -> bool {
+        self.since.map_or(true, |since| time >= since) && self.until.map_or(true, |until| time <= until)
+    }
+
+    /// Adds the tips to, and hides the hidden commits from, `revwalk`.
+    pub fn push_onto(&self, revwalk: &mut Revwalk) -> Result<(), git2::Error> {
+        for tip in self.tips.iter() {
+            try!(revwalk.push(*tip));
+        }
+        for hidden in self.hidden.iter() {
+            try!(revwalk.hide(*hidden));
+        }
+        Ok(())
+    }
+}
+
+// Every best common ancestor of `left` and `right`, of which criss-cross merges
+// leave more than one. Hiding only one of them would show the others.
+fn merge_bases(repo: &Repository, left: Oid, right: Oid) -> Result<Vec<Oid>, git2::Error> {
+    let mut revwalk = try!(repo.revwalk());
+    try!(revwalk.push(left));
+    let ancestors: HashSet<Oid> = revwalk.collect();
+    let mut revwalk = try!(repo.revwalk());
+    try!(revwalk.push(right));
+    let common: Vec<Oid> = revwalk.filter(|id| ancestors.contains(id)).collect();
+    // A common ancestor of another common ancestor is a parent of one, since
+    // everything in between is common as well.
+    let mut parents = HashSet::new();
+    for id in common.iter() {
+        let commit = try!(repo.find_commit(*id));
+        for index in 0..commit.parent_count() {
+            parents.insert(try!(commit.parent_id(index)));
+        }
+    }
+    Ok(common.into_iter().filter(|id| !parents.contains(id)).collect())
+}
+
+// `..B` and `A..` leave out `HEAD`.
+fn or_head(spec: &str) -> &str {
+    if spec.is_empty() { "HEAD" } else { spec }
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 for a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Parses `--since` and `--until` dates, either `YYYY-MM-DD` (midnight UTC)
+/// or seconds from Epoch, into seconds from Epoch.
+pub fn parse_date(date: &str) -> Result<i64, String> {
+    if let Ok(seconds) = date.parse::<i64>() {
+        return Ok(seconds);
+    }
+    let parts: Vec<Option<i64>> = date.split('-').map(|part| part.parse::<i64>().ok()).collect();
+    match &parts[..] {
+        &[Some(year), Some(month), Some(day)] if month >= 1 && month <= 12 && day >= 1 && day <= days_in_month(year, month) => {
+            Ok(days_from_civil(year, month, day) * 24 * 60 * 60)
+        },
+        _ => Err(format!("Invalid date `{}`, expected YYYY-MM-DD.", date)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use git2::Oid;
+
+    use fixture;
+    use super::{parse_date, Revisions};
+
+    #[test]
+    fn dates() {
+        assert_eq!(parse_date("1970-01-01"), Ok(0));
+        assert_eq!(parse_date("2015-04-20"), Ok(1429488000));
+        assert_eq!(parse_date("1429488000"), Ok(1429488000));
+        assert!(parse_date("2015-13-01").is_err());
+        assert!(parse_date("2015-02-31").is_err());
+        assert!(parse_date("2015-04-31").is_err());
+        assert!(parse_date("2015-02-29").is_err());
+        assert_eq!(parse_date("2016-02-29"), Ok(1456704000));
+        assert!(parse_date("1900-02-29").is_err());
+        assert!(parse_date("2000-02-29").is_ok());
+        assert!(parse_date("yesterday").is_err());
+    }
+
+    #[test]
+    fn symmetric_differences_hide_every_merge_base() {
+        let repo = fixture::repo("symmetric_differences_hide_every_merge_base");
+        let root = fixture::commit(&repo, &[("a", "0")], 0);
+        let left = fixture::commit_onto(&repo, &[root], &[("a", "1")], 60);
+        let right = fixture::commit_onto(&repo, &[root], &[("a", "2")], 120);
+        // A criss-cross merge, so `left` and `right` are both merge bases.
+        let left_merge = fixture::commit_onto(&repo, &[left, right], &[("a", "3")], 180);
+        let right_merge = fixture::commit_onto(&repo, &[right, left], &[("a", "4")], 240);
+        let left_tip = fixture::commit_onto(&repo, &[left_merge], &[("a", "5")], 300);
+        let right_tip = fixture::commit_onto(&repo, &[right_merge], &[("a", "6")], 360);
+
+        let spec = format!("{}...{}", left_tip, right_tip);
+        let revisions = Revisions::resolve(&repo, &[spec]).unwrap();
+        let mut revwalk = repo.revwalk().unwrap();
+        revisions.push_onto(&mut revwalk).unwrap();
+        let walked: HashSet<Oid> = revwalk.collect();
+        let expected: HashSet<Oid> = vec![left_merge, right_merge, left_tip, right_tip].into_iter().collect();
+        assert_eq!(walked, expected);
+    }
+}
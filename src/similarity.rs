@@ -0,0 +1,158 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher, SipHasher};
+
+// How many tokens are in each shingle.
+const SHINGLE_SIZE: usize = 3;
+
+// Splits a key into words and single punctuation characters.
+fn tokenize(key: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in key.char_indices() {
+        let word = c.is_alphanumeric() || c == '_';
+        match (start, word) {
+            (None, true) => start = Some(index),
+            (Some(begin), false) => {
+                tokens.push(&key[begin..index]);
+                start = None;
+            },
+            _ => (),
+        }
+        if !word && !c.is_whitespace() {
+            tokens.push(&key[index..index + c.len_utf8()]);
+        }
+    }
+    if let Some(begin) = start {
+        tokens.push(&key[begin..]);
+    }
+    tokens
+}
+
+fn shingles(key: &str) -> HashSet<Vec<&str>> {
+    let tokens = tokenize(key);
+    if tokens.len() < SHINGLE_SIZE {
+        // Too short to shingle, so compare the whole thing.
+        let mut set = HashSet::new();
+        set.insert(tokens);
+        return set;
+    }
+    tokens.windows(SHINGLE_SIZE).map(|window| window.to_vec()).collect()
+}
+
+/// The shingles of a key, for comparing it against many others without
+/// shingling it again each time.
+pub struct Shingles<'a> {
+    key: &'a str,
+    set: HashSet<Vec<&'a str>>,
+}
+
+impl<'a> Shingles<'a> {
+    pub fn new(key: &'a str) -> Shingles<'a> {
+        Shingles {
+            key: key,
+            set: shingles(key),
+        }
+    }
+
+    /// The Jaccard similarity of the shingles of two keys, from `0.0` (nothing
+    /// in common) to `1.0` (identical, ignoring whitespace).
+    pub fn similarity(&self, other: &Shingles) -> f64 {
+        if self.key == other.key { return 1.0; }
+        let union = self.set.union(&other.set).count();
+        if union == 0 { return 1.0; }
+        self.set.intersection(&other.set).count() as f64 / union as f64
+    }
+}
+
+/// The share of lines two texts have in common, from `0.0` to `1.0`, like the
+/// similarity git gives renamed files.
+pub fn line_similarity(a: &str, b: &str) -> f64 {
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in a.lines() {
+        let count = counts.get(line).cloned().unwrap_or(0);
+        counts.insert(line, count + 1);
+    }
+    let mut common = 0;
+    for line in b.lines() {
+        let count = counts.get(line).cloned().unwrap_or(0);
+        if count > 0 {
+            common += 1;
+            counts.insert(line, count - 1);
+        }
+    }
+    let total = a.lines().count() + b.lines().count();
+    if total == 0 { return 1.0; }
+    (2 * common) as f64 / total as f64
+}
+
+// Base of the polynomial used for rolling hashes.
+const BASE: u64 = 1000003;
+
+pub fn hash_line(key: &str) -> u64 {
+    let mut hasher = SipHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Rolling hashes of every window of `size` consecutive line hashes, so the
+/// hash at `i` covers `lines[i..i + size]`.
+pub fn rolling_hashes(lines: &[u64], size: usize) -> Vec<u64> {
+    if size == 0 || lines.len() < size { return Vec::new(); }
+    // The weight of the line leaving the window, `BASE ^ (size - 1)`.
+    let mut power: u64 = 1;
+    for _ in 1..size { power = power.wrapping_mul(BASE); }
+    let mut hash: u64 = 0;
+    for line in lines[..size].iter() {
+        hash = hash.wrapping_mul(BASE).wrapping_add(*line);
+    }
+    let mut hashes = vec![hash];
+    for index in size..lines.len() {
+        hash = hash.wrapping_sub(lines[index - size].wrapping_mul(power))
+            .wrapping_mul(BASE)
+            .wrapping_add(lines[index]);
+        hashes.push(hash);
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Shingles, line_similarity, rolling_hashes};
+
+    fn similarity(a: &str, b: &str) -> f64 {
+        Shingles::new(a).similarity(&Shingles::new(b))
+    }
+
+    #[test]
+    fn identical() {
+        assert_eq!(similarity("let a = b + c;", "let a = b + c;"), 1.0);
+    }
+
+    #[test]
+    fn lightly_edited() {
+        let old = "let total = items.iter().map(|x| x.size).sum(); println!(\"{}\", total);";
+        let new = "let total = items.iter().map(|x| x.len).sum(); println!(\"{}\", total);";
+        let score = similarity(old, new);
+        assert!(score > 0.5 && score < 1.0);
+    }
+
+    #[test]
+    fn unrelated() {
+        assert!(similarity("fn main() { run(); }", "struct Point { x: u8, y: u8 }") < 0.2);
+    }
+
+    #[test]
+    fn rolling_hashes_match_windows() {
+        let lines = [7, 1, 2, 3, 9];
+        let hashes = rolling_hashes(&lines, 3);
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes[1], rolling_hashes(&[1, 2, 3], 3)[0]);
+        assert!(hashes[0] != hashes[1]);
+    }
+
+    #[test]
+    fn shared_lines() {
+        assert_eq!(line_similarity("a\nb\nc\nd\n", "a\nb\nc\nd\n"), 1.0);
+        assert_eq!(line_similarity("a\nb\nc\nd\n", "a\nb\nx\ny\n"), 0.5);
+    }
+}
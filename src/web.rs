@@ -9,10 +9,14 @@ use iron::status;
 use iron::mime::{Mime, TopLevel, SubLevel};
 use mount::Mount;
 
-use git2::{Repository, Oid};
+use git2::Repository;
 use rustc_serialize::json;
+use num_cpus;
 
-use processor;
+use transit::processor;
+use transit::revisions::{self, Revisions};
+use transit::cache::Cache;
+use transit::normalizer::rust::Rust;
 
 const INDEX: &'static str = include_str!("../assets/index.html");
 const D3JS: &'static str = include_str!("../assets/d3.v3.js");
@@ -56,33 +60,107 @@ fn api(req: &mut Request) -> IronResult<Response> {
     let mut repo = None;
     let mut old = None;
     let mut new = None;
+    let mut revs = Vec::new();
+    let mut since = None;
+    let mut until = None;
+    // Nothing is written to the repo unless asked for.
+    let mut cached = false;
+    let mut options = processor::Options::default();
     for (key, val) in query_pairs {
         match &key[..] {
             "repo" => repo = match Repository::discover(&val) {
                 Ok(repo) => Some(repo),
                 Err(_) => return Ok(Response::with((status::BadRequest, "Repository Invalid."))),
             },
-            "old" => old = match Oid::from_str(&val) {
-                Ok(commit) => Some(commit),
-                Err(_) => return Ok(Response::with((status::BadRequest, "Old Commit Invalid."))),
+            "old" => old = Some(val),
+            "new" => new = Some(val),
+            "rev" => revs.push(val),
+            "include" => options.paths.include.push(val),
+            "exclude" => options.paths.exclude.push(val),
+            "generated" => options.generated = val == "true",
+            "cache" => cached = val == "true",
+            "algorithm" => options.diff.algorithm = match processor::Algorithm::from_str(&val) {
+                Ok(algorithm) => algorithm,
+                Err(_) => return Ok(Response::with((status::BadRequest, "Algorithm Invalid."))),
             },
-            "new" => new = match Oid::from_str(&val) {
-                Ok(commit) => Some(commit),
-                Err(_) => return Ok(Response::with((status::BadRequest, "New Commit Invalid"))),
+            "context" => options.diff.context_lines = match val.parse::<u32>() {
+                Ok(lines) => lines,
+                Err(_) => return Ok(Response::with((status::BadRequest, "Context Invalid."))),
+            },
+            "whitespace" => options.diff.whitespace = match processor::Whitespace::from_str(&val) {
+                Ok(whitespace) => whitespace,
+                Err(_) => return Ok(Response::with((status::BadRequest, "Whitespace Invalid."))),
+            },
+            "jobs" => options.jobs = match val.parse::<usize>() {
+                Ok(jobs) if jobs >= 1 && jobs <= num_cpus::get() => jobs,
+                _ => return Ok(Response::with((status::BadRequest, "Jobs Invalid."))),
+            },
+            "ignore_blank_lines" => options.diff.ignore_blank_lines = val == "true",
+            "since" => since = match revisions::parse_date(&val) {
+                Ok(date) => Some(date),
+                Err(_) => return Ok(Response::with((status::BadRequest, "Since Date Invalid."))),
+            },
+            "until" => until = match revisions::parse_date(&val) {
+                Ok(date) => Some(date),
+                Err(_) => return Ok(Response::with((status::BadRequest, "Until Date Invalid."))),
+            },
+            "deep" => if val == "true" {
+                options.registry.register(Box::new(Rust::deep()));
+            },
+            "copies" => options.copies = val == "true",
+            "window" => options.window = match val.parse::<usize>() {
+                Ok(commits) => Some(processor::Window::Commits(commits)),
+                Err(_) => return Ok(Response::with((status::BadRequest, "Window Invalid."))),
+            },
+            "window_days" => options.window = match val.parse::<u32>() {
+                Ok(days) => Some(processor::Window::Days(days)),
+                Err(_) => return Ok(Response::with((status::BadRequest, "Window Invalid."))),
+            },
+            "rename_threshold" => options.rename_threshold = match val.parse::<u16>() {
+                Ok(threshold) if threshold <= 100 => threshold,
+                _ => return Ok(Response::with((status::BadRequest, "Rename Threshold Invalid."))),
+            },
+            "copy_threshold" => options.copy_threshold = match val.parse::<u16>() {
+                Ok(threshold) if threshold <= 100 => threshold,
+                _ => return Ok(Response::with((status::BadRequest, "Copy Threshold Invalid."))),
+            },
+            "parents" => options.parents = match processor::Parents::from_str(&val) {
+                Ok(parents) => parents,
+                Err(_) => return Ok(Response::with((status::BadRequest, "Parents Invalid."))),
+            },
+            "threshold" => options.threshold = match val.parse::<f64>() {
+                Ok(threshold) if threshold > 0.0 && threshold <= 1.0 => threshold,
+                _ => return Ok(Response::with((status::BadRequest, "Threshold Invalid."))),
             },
             _ => return Ok(Response::with((status::BadRequest, "Your input falls short of expectations"))),
         }
     }
+    if let Some(ref repo) = repo {
+        if cached { options.cache = Some(Cache::for_repo(repo)); }
+    }
     match (repo, old, new) {
         (Some(repo), Some(old), Some(new)) => {
-            let out = match processor::commits(repo, old, new) {
+            if since.is_some() || until.is_some() {
+                return Ok(Response::with((status::BadRequest, "Since and Until Only Limit Walks.")));
+            }
+            let revisions = match Revisions::pair(&repo, &old, &new) {
+                Ok(revisions) => revisions,
+                Err(_) => return Ok(Response::with((status::BadRequest, "Commits Invalid."))),
+            };
+            let out = match processor::commits(repo, &revisions, &options) {
                 Ok(output) => output,
                 Err(_) => return Ok(Response::with((status::InternalServerError, "Hold steadfast and report bugs."))),
             };
             Ok(Response::with((status::Ok, json::encode(&out).unwrap())))
         },
         (Some(repo), None, None) => {
-            let out = match processor::repo(repo) {
+            let mut revisions = match Revisions::resolve(&repo, &revs) {
+                Ok(revisions) => revisions,
+                Err(_) => return Ok(Response::with((status::BadRequest, "Revisions Invalid."))),
+            };
+            revisions.since = since;
+            revisions.until = until;
+            let out = match processor::repo(repo, &revisions, &options) {
                 Ok(output) => output,
                 Err(_) => return Ok(Response::with((status::InternalServerError, "Hold steadfast and report bugs."))),
             };